@@ -1,6 +1,9 @@
+use std::collections::HashMap;
 use std::io::prelude::*;
 use std::net::TcpListener;
 use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use serde_json;
 
@@ -8,6 +11,179 @@ use regex::Regex;
 
 use tesla::*;
 
+const FAKE_ACCESS_TOKEN: &str = "magic_token_for_api_calls";
+const FAKE_REFRESH_TOKEN: &str = "magic_refresh_token";
+const FAKE_EXPIRED_TOKEN: &str = "expired_token";
+const FAKE_CSRF: &str = "fake_csrf_token";
+const FAKE_SESSION_COOKIE: &str = "tesla-auth.sid=fake_session";
+const FAKE_AUTHORIZATION_CODE: &str = "fake_authorization_code";
+const FAKE_TRANSACTION_ID: &str = "fake_transaction";
+const FAKE_MFA_EMAIL: &str = "mfa@example.com";
+const FAKE_MFA_FACTOR_ID: &str = "fake_factor";
+const FAKE_MFA_PASSCODE: &str = "123456";
+// vehicles answering every request with an error, to exercise the error handling
+const FAKE_ASLEEP_VEHICLE_ID: u64 = 408;
+const FAKE_RATE_LIMITED_VEHICLE_ID: u64 = 429;
+const FAKE_BROKEN_VEHICLE_ID: u64 = 500;
+const FAKE_GARBLED_VEHICLE_ID: u64 = 200;
+const FAKE_RETRY_AFTER_SECONDS: u64 = 3;
+// a vehicle failing every other request with 503
+const FAKE_FLAKY_VEHICLE_ID: u64 = 503;
+const FAKE_ENERGY_SITE_ID: u64 = 90001;
+
+// parameters expected in the JSON body of the commands
+const COMMAND_PARAMETERS: &[(&str, &str)] = &[
+    ("set_charge_limit", "percent"),
+    ("set_charging_amps", "charging_amps"),
+    ("set_temps", "driver_temp"),
+    ("set_temps", "passenger_temp"),
+    ("remote_seat_heater_request", "heater"),
+    ("remote_seat_heater_request", "level"),
+    ("remote_seat_cooler_request", "seat_position"),
+    ("remote_seat_cooler_request", "seat_cooler_level"),
+    ("remote_steering_wheel_heater_request", "on"),
+    ("set_preconditioning_max", "on"),
+    ("set_climate_keeper_mode", "climate_keeper_mode"),
+    ("set_cabin_overheat_protection", "on"),
+    ("set_bioweapon_mode", "on"),
+    ("actuate_trunk", "which_trunk"),
+    ("window_control", "command"),
+    ("window_control", "lat"),
+    ("window_control", "lon"),
+    ("sun_roof_control", "state"),
+    ("set_sentry_mode", "on"),
+    ("set_valet_mode", "on"),
+    ("speed_limit_activate", "pin"),
+    ("speed_limit_deactivate", "pin"),
+    ("speed_limit_set_limit", "limit_mph"),
+    ("speed_limit_clear_pin", "pin"),
+    ("set_pin_to_drive", "on"),
+    ("set_pin_to_drive", "password"),
+    ("adjust_volume", "volume"),
+    ("set_scheduled_charging", "enable"),
+    ("set_scheduled_charging", "time"),
+    ("set_scheduled_departure", "enable"),
+    ("set_scheduled_departure", "departure_time"),
+    ("set_scheduled_departure", "end_off_peak_time"),
+    ("navigation_request", "type"),
+    ("navigation_request", "value"),
+    ("navigation_request", "timestamp_ms"),
+    ("navigation_sc_request", "id"),
+    ("trigger_homelink", "lat"),
+    ("trigger_homelink", "lon"),
+    ("set_vehicle_name", "vehicle_name"),
+    ("guest_mode", "enable"),
+    ("schedule_software_update", "offset_sec"),
+    // energy site commands
+    ("backup", "backup_reserve_percent"),
+    ("operation", "default_real_mode"),
+    ("storm_mode", "enabled"),
+];
+
+const FAKE_CHARGING_SITES: &str = r#"{
+    "congestion_sync_time_utc_secs": 1615720316,
+    "destination_charging": [
+        { "location": { "lat": 37.3874, "long": -122.0583 }, "name": "Mountain View, CA - Hotel", "type": "destination",
+          "distance_miles": 2.4, "amenities": "restrooms,wifi" },
+        { "location": { "lat": 37.4221, "long": -122.0841 }, "name": "Palo Alto, CA - Garage", "type": "destination",
+          "distance_miles": 0.8 }
+    ],
+    "superchargers": [
+        { "location": { "lat": 37.4436, "long": -122.1707 }, "name": "Palo Alto, CA - University Avenue", "type": "supercharger",
+          "distance_miles": 5.1, "available_stalls": 3, "total_stalls": 12, "site_closed": false },
+        { "location": { "lat": 37.3948, "long": -122.0779 }, "name": "Mountain View, CA", "type": "supercharger",
+          "distance_miles": 1.7, "available_stalls": 0, "total_stalls": 8, "site_closed": false }
+    ],
+    "timestamp": 1615720335417
+}"#;
+
+const FAKE_ENERGY_SITE_STATUS: &str = r#"{
+    "resource_type": "battery", "site_name": "Home", "gateway_id": "1118431-00-L--TG0123456789AB",
+    "energy_left": 9842.5, "total_pack_energy": 13500, "percentage_charged": 72.9,
+    "battery_type": "ac_powerwall", "backup_capable": true, "battery_power": -1250
+}"#;
+
+const FAKE_ENERGY_LIVE_STATUS: &str = r#"{
+    "solar_power": 3620, "energy_left": 9842.5, "total_pack_energy": 13500, "percentage_charged": 72.9,
+    "backup_capable": true, "battery_power": -1250, "load_power": 1870, "grid_status": "Active",
+    "grid_power": -500, "island_status": "on_grid", "storm_mode_active": false,
+    "timestamp": "2021-03-14T12:31:56+01:00"
+}"#;
+
+const FAKE_ENERGY_SITE_INFO: &str = r#"{
+    "id": "STE20210314-00042", "site_name": "Home", "backup_reserve_percent": 20,
+    "default_real_mode": "self_consumption", "installation_date": "2021-03-01T10:00:00+01:00",
+    "version": "21.9.1", "battery_count": 1, "nameplate_power": 5000, "nameplate_energy": 13500,
+    "user_settings": { "storm_mode_enabled": true }
+}"#;
+
+const FAKE_ENERGY_HISTORY: &str = r#"{
+    "period": "day",
+    "time_series": [
+        { "timestamp": "2021-03-14T00:00:00+01:00", "solar_energy_exported": 18420, "grid_energy_imported": 2310,
+          "grid_energy_exported_from_solar": 6100, "battery_energy_exported": 7200,
+          "battery_energy_imported_from_solar": 8100, "consumer_energy_imported_from_grid": 2310,
+          "consumer_energy_imported_from_solar": 4220, "consumer_energy_imported_from_battery": 7200 }
+    ]
+}"#;
+
+static FLAKY_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// A command received by a vehicle, with its JSON body and its query string, for the tests to check.
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+struct ReceivedCommand {
+    name: String,
+    params: serde_json::Value,
+    query: HashMap<String, String>,
+}
+
+const FAKE_CAPTCHA_EMAIL: &str = "captcha@example.com";
+const FAKE_CAPTCHA_ANSWER: &str = "x7kq";
+const FAKE_CAPTCHA_IMAGE: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="120" height="40"><text x="10" y="30">x7kq</text></svg>"#;
+
+const LOGIN_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<body>
+<form method="post" id="form">
+  <input type="hidden" name="_csrf" value="fake_csrf_token" />
+  <input type="hidden" name="_phase" value="authenticate" />
+  <input type="hidden" name="_process" value="1" />
+  <input type="hidden" name="transaction_id" value="fake_transaction" />
+  <input type="hidden" name="cancel" value="" />
+  <input type="text" name="identity" />
+  <input type="password" name="credential" />
+</form>
+</body>
+</html>"#;
+
+const CAPTCHA_LOGIN_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<body>
+<form method="post" id="form">
+  <input type="hidden" name="_csrf" value="fake_csrf_token" />
+  <input type="hidden" name="_phase" value="authenticate" />
+  <input type="hidden" name="_process" value="1" />
+  <input type="hidden" name="transaction_id" value="fake_transaction" />
+  <input type="hidden" name="cancel" value="" />
+  <input type="text" name="identity" />
+  <input type="password" name="credential" />
+  <img src="/captcha" />
+  <input type="text" name="captcha" />
+</form>
+</body>
+</html>"#;
+
+const MFA_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<body>
+<form method="post" action="/oauth2/v3/authorize/mfa/verify" id="form">
+  <input type="hidden" name="transaction_id" value="fake_transaction" />
+  <input type="text" name="passcode" />
+</form>
+</body>
+</html>"#;
+
 fn main() {
     let bind_address = "127.0.0.1";
     let bind_port = 4321;
@@ -15,20 +191,89 @@ fn main() {
     let listener = TcpListener::bind(&listen_address).unwrap();
 
     println!("Fake server is now listening for requests on {}", listen_address);
+    serve(listener, Arc::default());
+}
+
+fn serve(listener: TcpListener, received: Arc<Mutex<Vec<ReceivedCommand>>>) {
+    // the fake vehicle can be renamed
+    let mut vehicle_name = "Test CAR".to_string();
     for stream in listener.incoming() {
         let stream = stream.unwrap();
 
-        handle_connection(stream);
+        handle_connection(stream, &mut vehicle_name, &received);
     }
 }
 
-fn handle_connection(mut stream: TcpStream) {
+/// Reads the request head and, if any, the body announced by its Content-Length.
+fn read_request(stream: &mut TcpStream) -> (String, String) {
+    let mut data: Vec<u8> = Vec::new();
     let mut buffer = [0; 1024];
-    stream.read(&mut buffer).unwrap();
+    let content_length_re = Regex::new(r"(?i)\r\ncontent-length:\s*(\d+)").unwrap();
+
+    loop {
+        let read = stream.read(&mut buffer).unwrap();
+        if read == 0 {
+            break;
+        }
+        data.extend_from_slice(&buffer[..read]);
+
+        let http_content = String::from_utf8_lossy(&data);
+        if let Some(head_end) = http_content.find("\r\n\r\n") {
+            let content_length = content_length_re.captures(&http_content[..head_end])
+                .map(|c| c[1].parse::<usize>().unwrap())
+                .unwrap_or(0);
+            if data.len() >= head_end + 4 + content_length {
+                break;
+            }
+        }
+    }
+
+    let http_content = String::from_utf8_lossy(&data).to_string();
+    match http_content.find("\r\n\r\n") {
+        Some(head_end) => (http_content[..head_end].to_string(), http_content[head_end + 4..].to_string()),
+        None => (http_content, String::new())
+    }
+}
+
+fn parse_form(content: &str) -> HashMap<String, String> {
+    content.split('&')
+        .filter_map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            Some((url_decode(kv.next()?), url_decode(kv.next().unwrap_or(""))))
+        })
+        .collect()
+}
+
+fn url_decode(value: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut chars = value.bytes();
+    while let Some(c) = chars.next() {
+        match c {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex: String = chars.by_ref().take(2).map(char::from).collect();
+                bytes.push(u8::from_str_radix(hex.as_str(), 16).unwrap_or(b'?'));
+            }
+            _ => bytes.push(c)
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+fn fake_oauth_token() -> OAuthToken {
+    OAuthToken {
+        access_token: FAKE_ACCESS_TOKEN.to_string(),
+        refresh_token: FAKE_REFRESH_TOKEN.to_string(),
+        expires_in: 28800,
+        token_type: "Bearer".to_string(),
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, vehicle_name: &mut String, received: &Mutex<Vec<ReceivedCommand>>) {
+    let (head, body) = read_request(&mut stream);
 
-    let http_content = std::str::from_utf8(&buffer).unwrap();
     let re: Regex = Regex::new(r"^(\w+)\s+(.+)\s+HTTP").unwrap();
-    let regex_captures = re.captures(http_content).unwrap();
+    let regex_captures = re.captures(head.as_str()).unwrap();
     let method = regex_captures.get(1).unwrap().as_str();
     let url = regex_captures.get(2).unwrap().as_str();
 
@@ -38,17 +283,114 @@ fn handle_connection(mut stream: TcpStream) {
         id: 0,
         vehicle_id: 0,
         vin: "ABC1234567890".to_string(),
-        display_name: "Test CAR".to_string(),
+        display_name: vehicle_name.clone(),
         state: "online".to_string(),
         id_s: "".to_string(),
         tokens: vec![]
     };
 
     let mut status_line = "HTTP/1.1 200 OK";
+    let mut headers = String::new();
     let mut contents = String::new();
-    if method == "POST" && Regex::new(r"^/oauth/token$").unwrap().is_match(url) {
+    let expired_token = Regex::new(format!(r"(?i)\r\nauthorization:\s*bearer {}\r\n", FAKE_EXPIRED_TOKEN).as_str()).unwrap();
+    if url.starts_with("/api/") && expired_token.is_match(head.as_str()) {
+        status_line = "HTTP/1.1 401 UNAUTHORIZED";
+        headers.push_str("WWW-Authenticate: Bearer error=\"invalid_token\"\r\n");
+    } else if let Some(c) = Regex::new(r"^/api/\d+/vehicles/(\d+)/").unwrap().captures(url)
+        .filter(|c| [FAKE_ASLEEP_VEHICLE_ID, FAKE_RATE_LIMITED_VEHICLE_ID, FAKE_BROKEN_VEHICLE_ID, FAKE_GARBLED_VEHICLE_ID]
+            .contains(&c[1].parse().unwrap_or(0))) {
+        headers.push_str("Content-Type: application/json\r\n");
+        match c[1].parse::<u64>().unwrap() {
+            FAKE_ASLEEP_VEHICLE_ID => {
+                status_line = "HTTP/1.1 408 REQUEST TIMEOUT";
+                contents = r#"{"response":null,"error":"vehicle unavailable: {:error=>\"vehicle unavailable:\"}","error_description":""}"#.to_string();
+            }
+            FAKE_RATE_LIMITED_VEHICLE_ID => {
+                status_line = "HTTP/1.1 429 TOO MANY REQUESTS";
+                headers.push_str(format!("Retry-After: {}\r\n", FAKE_RETRY_AFTER_SECONDS).as_str());
+                contents = r#"{"response":null,"error":"rate limit exceeded","error_description":""}"#.to_string();
+            }
+            FAKE_BROKEN_VEHICLE_ID => {
+                status_line = "HTTP/1.1 500 INTERNAL SERVER ERROR";
+                contents = "upstream internal error".to_string();
+            }
+            _ => contents = r#"{"response":{"id":"not a number"}}"#.to_string(),
+        }
+    } else if method == "GET" && Regex::new(r"^/oauth2/v3/authorize\?").unwrap().is_match(url) {
+        // step 1 of the SSO login: the form with its hidden fields, and a session cookie
+        headers.push_str(format!("Set-Cookie: {}; Path=/\r\n", FAKE_SESSION_COOKIE).as_str());
+        headers.push_str("Content-Type: text/html\r\n");
+        contents = if url.contains(format!("login_hint={}", FAKE_CAPTCHA_EMAIL.replace("@", "%40")).as_str()) {
+            CAPTCHA_LOGIN_PAGE.to_string()
+        } else {
+            LOGIN_PAGE.to_string()
+        };
+    } else if method == "GET" && Regex::new(r"^/captcha$").unwrap().is_match(url) {
+        headers.push_str("Content-Type: image/svg+xml\r\n");
+        contents = FAKE_CAPTCHA_IMAGE.to_string();
+    } else if method == "POST" && Regex::new(r"^/oauth2/v3/authorize\?").unwrap().is_match(url) {
+        // step 2: the credentials are posted back with the hidden fields and the session cookie,
+        // or only the transaction id once the multi-factor passcode is verified
+        let form = parse_form(body.as_str());
+        let has_session = head.contains(FAKE_SESSION_COOKIE);
+        let has_csrf = form.get("_csrf").map(|v| v == FAKE_CSRF).unwrap_or(false);
+        let has_credentials = form.get("identity").map(|v| !v.is_empty()).unwrap_or(false)
+            && form.get("credential").map(|v| !v.is_empty()).unwrap_or(false);
+        let mfa_done = !form.contains_key("identity")
+            && form.get("transaction_id").map(|v| v == FAKE_TRANSACTION_ID).unwrap_or(false);
+
+        let captcha_failed = has_credentials && form["identity"] == FAKE_CAPTCHA_EMAIL
+            && form.get("captcha").map(|v| v != FAKE_CAPTCHA_ANSWER).unwrap_or(true);
+
+        if has_session && has_csrf && captcha_failed {
+            headers.push_str("Content-Type: text/html\r\n");
+            contents = CAPTCHA_LOGIN_PAGE.to_string();
+        } else if has_session && has_csrf && has_credentials && form["identity"] == FAKE_MFA_EMAIL {
+            headers.push_str("Content-Type: text/html\r\n");
+            contents = MFA_PAGE.to_string();
+        } else if has_session && ((has_csrf && has_credentials) || mfa_done) {
+            let state = Regex::new(r"[?&]state=([^&]*)").unwrap().captures(url)
+                .map(|c| c[1].to_string())
+                .unwrap_or_default();
+            status_line = "HTTP/1.1 302 FOUND";
+            headers.push_str(format!("Location: /void/callback?code={}&state={}\r\n", FAKE_AUTHORIZATION_CODE, state).as_str());
+        } else {
+            status_line = "HTTP/1.1 401 UNAUTHORIZED";
+        }
+    } else if method == "GET" && Regex::new(r"^/oauth2/v3/authorize/mfa/factors\?transaction_id=fake_transaction$").unwrap().is_match(url) {
+        let factors = vec![MfaFactor {
+            id: FAKE_MFA_FACTOR_ID.to_string(),
+            name: "Fake authenticator".to_string(),
+            factor_type: "token:software".to_string(),
+        }];
+        headers.push_str("Content-Type: application/json\r\n");
+        contents = format!("{{ \"data\" : {} }}", serde_json::to_string(&factors).unwrap());
+    } else if method == "POST" && Regex::new(r"^/oauth2/v3/authorize/mfa/verify$").unwrap().is_match(url) {
+        let params: serde_json::Value = serde_json::from_str(body.as_str()).unwrap_or_default();
+        let valid = params["transaction_id"] == FAKE_TRANSACTION_ID
+            && params["factor_id"] == FAKE_MFA_FACTOR_ID
+            && params["passcode"] == FAKE_MFA_PASSCODE;
+        headers.push_str("Content-Type: application/json\r\n");
+        contents = format!("{{ \"data\" : {{ \"approved\": {}, \"valid\": {}, \"flagged\": false }} }}", valid, valid);
+    } else if method == "POST" && Regex::new(r"^/oauth2/v3/token$").unwrap().is_match(url) {
+        // step 3: exchange of the authorization code, or refresh of a token
+        let params: serde_json::Value = serde_json::from_str(body.as_str()).unwrap_or_default();
+        let valid = match params["grant_type"].as_str() {
+            Some("authorization_code") => params["code"] == FAKE_AUTHORIZATION_CODE
+                && params["code_verifier"].as_str().map(|v| !v.is_empty()).unwrap_or(false),
+            Some("refresh_token") => params["refresh_token"] == FAKE_REFRESH_TOKEN,
+            _ => false
+        };
+
+        if valid {
+            headers.push_str("Content-Type: application/json\r\n");
+            contents = serde_json::to_string(&fake_oauth_token()).unwrap();
+        } else {
+            status_line = "HTTP/1.1 401 UNAUTHORIZED";
+        }
+    } else if method == "POST" && Regex::new(r"^/oauth/token$").unwrap().is_match(url) {
         let auth_response = AuthResponse {
-            access_token: "magic_token_for_api_calls".to_string(),
+            access_token: FAKE_ACCESS_TOKEN.to_string(),
             token_type: "bearer".to_string(),
             expires_in: 3888000,
             created_at: 1571519135,
@@ -57,101 +399,726 @@ fn handle_connection(mut stream: TcpStream) {
         contents = serde_json::to_string(&auth_response).unwrap();
     } else if method == "POST" && Regex::new(r"^/api/\d+/vehicles/\d+/wake_up$").unwrap().is_match(url) {
         contents = format!("{{ \"response\" : {} }}", serde_json::to_string(&dummy_vehicle).unwrap());
+    } else if Regex::new(format!(r"^/api/\d+/vehicles/{}/", FAKE_FLAKY_VEHICLE_ID).as_str()).unwrap().is_match(url)
+        && FLAKY_REQUESTS.fetch_add(1, Ordering::SeqCst) & 1 == 0 {
+        status_line = "HTTP/1.1 503 SERVICE UNAVAILABLE";
+    } else if let Some(c) = Regex::new(r"^/api/\d+/vehicles/\d+/command/(\w+)(\?.*)?$").unwrap().captures(url).filter(|_| method == "POST") {
+        // the doors of the fake vehicle are already locked, every other command succeeds if given its parameters
+        let params: serde_json::Value = serde_json::from_str(body.as_str()).unwrap_or_default();
+        let missing_parameter = COMMAND_PARAMETERS.iter()
+            .filter(|(command, _)| *command == &c[1])
+            .any(|(_, parameter)| params.get(parameter).is_none())
+            // the password is sent in the query string
+            || (&c[1] == "remote_start_drive" && !Regex::new(r"[?&]password=[^&]+").unwrap().is_match(url));
+        if let Some(name) = params.get("vehicle_name").and_then(|n| n.as_str()).filter(|_| &c[1] == "set_vehicle_name") {
+            *vehicle_name = name.to_string();
+        }
+        received.lock().unwrap().push(ReceivedCommand {
+            name: c[1].to_string(),
+            query: c.get(2).map(|q| parse_form(&q.as_str()[1..])).unwrap_or_default(),
+            params,
+        });
+        contents = match &c[1] {
+            "door_lock" => r#"{ "response" : { "result": false, "reason": "already_set" } }"#.to_string(),
+            _ if missing_parameter => r#"{ "response" : { "result": false, "reason": "missing_parameter" } }"#.to_string(),
+            _ => r#"{ "response" : { "result": true, "reason": "" } }"#.to_string(),
+        };
+    } else if method == "GET" && Regex::new(r"^/api/\d+/products$").unwrap().is_match(url) {
+        let energy_site = format!(r#"{{ "energy_site_id": {}, "resource_type": "battery", "site_name": "Home", "id": "STE20210314-00042",
+            "energy_left": 9842.5, "total_pack_energy": 13500, "percentage_charged": 72.9 }}"#, FAKE_ENERGY_SITE_ID);
+        let wall_connector = r#"{ "din": "1529455-02-D--PGT21234567890", "part_name": "Gen 3 Wall Connector" }"#;
+        contents = format!("{{ \"response\" : [{}, {}, {}], \"count\": 3 }}",
+                           serde_json::to_string(&dummy_vehicle).unwrap(), energy_site, wall_connector);
+    } else if let Some(c) = Regex::new(r"^/api/\d+/energy_sites/\d+/(site_status|live_status|site_info|calendar_history)(\?.*)?$")
+        .unwrap().captures(url).filter(|_| method == "GET") {
+        let data = match &c[1] {
+            "site_status" => FAKE_ENERGY_SITE_STATUS,
+            "live_status" => FAKE_ENERGY_LIVE_STATUS,
+            "site_info" => FAKE_ENERGY_SITE_INFO,
+            _ => FAKE_ENERGY_HISTORY,
+        };
+        contents = format!("{{ \"response\" : {} }}", data);
+    } else if let Some(c) = Regex::new(r"^/api/\d+/energy_sites/\d+/(backup|operation|storm_mode)$").unwrap().captures(url).filter(|_| method == "POST") {
+        let params: serde_json::Value = serde_json::from_str(body.as_str()).unwrap_or_default();
+        let missing_parameter = COMMAND_PARAMETERS.iter()
+            .filter(|(command, _)| *command == &c[1])
+            .any(|(_, parameter)| params.get(parameter).is_none());
+        contents = if missing_parameter {
+            r#"{ "response" : { "code": 400, "message": "missing_parameter" } }"#.to_string()
+        } else {
+            r#"{ "response" : { "code": 201, "message": "Updated" } }"#.to_string()
+        };
     } else if method == "GET" && Regex::new(r"^/api/\d+/vehicles$").unwrap().is_match(url) {
         let vehicles = vec![dummy_vehicle];
         contents = format!("{{ \"response\" : {}, \"count\": {} }}", serde_json::to_string(&vehicles).unwrap(), vehicles.len());
     } else if method == "GET" && Regex::new(r"^/api/\d+/vehicles/\d+/$").unwrap().is_match(url) {
         contents = format!("{{ \"response\" : {} }}", serde_json::to_string(&dummy_vehicle).unwrap());
     } else if method == "GET" && Regex::new(r"^/api/\d+/vehicles/\d+/vehicle_data$").unwrap().is_match(url) {
-        let full_vehicle_data = FullVehicleData {
-            id: 0,
-            user_id: 0,
-            vehicle_id: 0,
-            state: "Driving".to_string(),
-            charge_state: StateOfCharge {
-                battery_heater_on: false,
-                battery_level: 50,
-                battery_range: 120.12,
-                charge_current_request: 0,
-                charge_current_request_max: 0,
-                charge_limit_soc: 0,
-                charge_port_door_open: false,
-                charge_port_latch: "".to_string(),
-                charge_rate: 0.0,
-                charger_actual_current: 0,
-                charger_power: 0,
-                charger_voltage: 0,
-                charging_state: "".to_string(),
-                est_battery_range: 0.0,
-                ideal_battery_range: 0.0,
-                minutes_to_full_charge: 0,
-                usable_battery_level: 0,
-                time_to_full_charge: 0.0
-            },
-            vehicle_state: VehicleState {
-                api_version: 0,
-                odometer: 23456.0,
-                sentry_mode: false,
-                locked: true,
-                car_version: "".to_string(),
-                df: 0,
-                dr: 0,
-                pf: 0,
-                pr: 0,
-                fd_window: 0,
-                fp_window: 0,
-                rd_window: 0,
-                rp_window: 0,
-                ft: 0,
-                rt: 0
-            },
-            drive_state: DriveState {
-                gps_as_of: 0,
-                heading: 0,
-                latitude: 0.0,
-                longitude: 0.0,
-                native_latitude: 0.0,
-                native_longitude: 0.0,
-                power: 0.0,
-                timestamp: 0,
-                shift_state: None,
-                speed: None
-            },
-            climate_state: ClimateState {
-                battery_heater: false,
-                defrost_mode: 0,
-                driver_temp_setting: 0.0,
-                inside_temp: 15.0,
-                is_auto_conditioning_on: false,
-                is_climate_on: false,
-                is_front_defroster_on: false,
-                is_preconditioning: false,
-                is_rear_defroster_on: false,
-                outside_temp: 10.0,
-                passenger_temp_setting: 0.0,
-                remote_heater_control_enabled: false,
-                seat_heater_left: 0,
-                seat_heater_right: 0
-            },
-            gui_settings: GuiSettings {
-                gui_charge_rate_units: "km/hr".to_string(),
-                gui_distance_units: "km/hr".to_string(),
-                gui_temperature_units: "C".to_string(),
-            },
-            vehicle_config: VehicleConfig {
-                car_type: "model3".to_string(),
-                exterior_color: "DeepBlue".to_string(),
-                wheel_type: "Pinwheel18".to_string(),
-                trim_badging: None
-            }
-        };
-        contents = format!("{{ \"response\" : {} }}", serde_json::to_string(&full_vehicle_data).unwrap());
+        contents = format!("{{ \"response\" : {} }}", serde_json::to_string(&fake_vehicle_data()).unwrap());
+    } else if let Some(c) = Regex::new(r"^/api/\d+/vehicles/\d+/data_request/(charge_state|climate_state|drive_state|vehicle_state|vehicle_config)$")
+        .unwrap().captures(url).filter(|_| method == "GET") {
+        let data = serde_json::to_value(fake_vehicle_data()).unwrap();
+        contents = format!("{{ \"response\" : {} }}", data[&c[1]]);
+    } else if method == "GET" && Regex::new(r"^/api/\d+/vehicles/\d+/mobile_enabled$").unwrap().is_match(url) {
+        contents = r#"{ "response" : true }"#.to_string();
+    } else if method == "GET" && Regex::new(r"^/api/\d+/vehicles/\d+/service_data$").unwrap().is_match(url) {
+        contents = r#"{ "response" : { "service_status": "not_in_service" } }"#.to_string();
+    } else if method == "GET" && Regex::new(r"^/api/\d+/vehicles/\d+/nearby_charging_sites$").unwrap().is_match(url) {
+        contents = format!("{{ \"response\" : {} }}", FAKE_CHARGING_SITES);
     } else {
         println!("No route found for {} {}", method, url);
         status_line = "HTTP/1.1 404 NOT FOUND";
     };
 
-    let response = format!("{}\r\nContent-Length: {}\r\n\r\n{}", status_line, contents.len(), contents);
-    stream.write(response.as_bytes()).unwrap();
+    let response = format!("{}\r\n{}Connection: close\r\nContent-Length: {}\r\n\r\n{}", status_line, headers, contents.len(), contents);
+    stream.write_all(response.as_bytes()).unwrap();
     stream.flush().unwrap();
 }
+
+/// The data of the fake vehicle, served whole by `vehicle_data` or by parts by `data_request/...`.
+fn fake_vehicle_data() -> FullVehicleData {
+    FullVehicleData {
+        id: 0,
+        user_id: 0,
+        vehicle_id: 0,
+        state: "Driving".to_string(),
+        charge_state: StateOfCharge {
+            battery_heater_on: false,
+            battery_level: 50,
+            battery_range: 120.12,
+            charge_current_request: 0,
+            charge_current_request_max: 0,
+            charge_limit_soc: 0,
+            charge_port_door_open: false,
+            charge_port_latch: "".to_string(),
+            charge_rate: 0.0,
+            charger_actual_current: 0,
+            charger_power: 0,
+            charger_voltage: 0,
+            charging_state: "".to_string(),
+            est_battery_range: 0.0,
+            ideal_battery_range: 0.0,
+            minutes_to_full_charge: 0,
+            usable_battery_level: 0,
+            time_to_full_charge: 0.0,
+            scheduled_charging_mode: Some("StartAt".to_string()),
+            scheduled_charging_pending: Some(true),
+            scheduled_charging_start_time_app: Some(TimeOfDay::new(23, 30).unwrap()),
+            scheduled_departure_time_minutes: Some(TimeOfDay::new(7, 45).unwrap()),
+            preconditioning_enabled: Some(false),
+            preconditioning_times: Some("all_week".to_string()),
+            off_peak_charging_enabled: Some(false),
+            off_peak_charging_times: Some("all_week".to_string()),
+            off_peak_hours_end_time: Some(TimeOfDay::new(6, 0).unwrap())
+        },
+        vehicle_state: VehicleState {
+            api_version: 0,
+            odometer: 23456.0,
+            sentry_mode: false,
+            locked: true,
+            car_version: "".to_string(),
+            df: 0,
+            dr: 0,
+            pf: 0,
+            pr: 0,
+            fd_window: 0,
+            fp_window: 0,
+            rd_window: 0,
+            rp_window: 0,
+            ft: 0,
+            rt: 0,
+            media_info: Some(MediaState {
+                media_playback_status: Some("Playing".to_string()),
+                now_playing_source: Some("Spotify".to_string()),
+                now_playing_title: Some("Fake song".to_string()),
+                now_playing_artist: Some("Fake band".to_string()),
+                now_playing_duration: Some(215000),
+                now_playing_elapsed: Some(42000),
+                audio_volume: Some(4.5),
+                audio_volume_max: Some(10.333333),
+                ..MediaState::default()
+            }),
+            software_update: Some(SoftwareUpdate {
+                status: SoftwareUpdateStatus::Available,
+                version: "2021.4.15".to_string(),
+                download_perc: 100,
+                install_perc: 1,
+                expected_duration_sec: 2700,
+                scheduled_time_ms: None
+            })
+        },
+        drive_state: DriveState {
+            gps_as_of: 0,
+            heading: 0,
+            latitude: 0.0,
+            longitude: 0.0,
+            native_latitude: 0.0,
+            native_longitude: 0.0,
+            power: 0.0,
+            timestamp: 0,
+            shift_state: None,
+            speed: None
+        },
+        climate_state: ClimateState {
+            battery_heater: false,
+            defrost_mode: 0,
+            driver_temp_setting: 0.0,
+            inside_temp: 15.0,
+            is_auto_conditioning_on: false,
+            is_climate_on: false,
+            is_front_defroster_on: false,
+            is_preconditioning: false,
+            is_rear_defroster_on: false,
+            outside_temp: 10.0,
+            passenger_temp_setting: 0.0,
+            remote_heater_control_enabled: false,
+            seat_heater_left: 0,
+            seat_heater_right: 0
+        },
+        gui_settings: GuiSettings {
+            gui_charge_rate_units: "km/hr".to_string(),
+            gui_distance_units: "km/hr".to_string(),
+            gui_temperature_units: "C".to_string(),
+        },
+        vehicle_config: VehicleConfig {
+            car_type: "model3".to_string(),
+            exterior_color: "DeepBlue".to_string(),
+            wheel_type: "Pinwheel18".to_string(),
+            trim_badging: None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    use tesla::{AuthConfig, AuthRegion, BrowserLogin, ClimateKeeperMode, Destination, LoginChallenge, LoginStep, RefreshingToken,
+                Password, Pin, RejectionReason, RetryPolicy, ScheduledDeparture, Seat, SeatLevel, StoredToken, TeslaClient, TeslaError,
+                TimeOfDay, TokenStore, Trunk, WindowCommand};
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn start_server() -> String {
+        start_recording_server().0
+    }
+
+    /// Starts a server, and returns the commands its vehicles receive along with its API root.
+    fn start_recording_server() -> (String, Arc<Mutex<Vec<ReceivedCommand>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let server_received = received.clone();
+        thread::spawn(move || serve(listener, server_received));
+        (format!("http://127.0.0.1:{}/api/1/", port), received)
+    }
+
+    #[tokio::test]
+    async fn test_login_flow() {
+        let api_root = start_server();
+
+        let token = TeslaClient::authenticate_using_api_root(api_root.as_str(), "driver@example.com", "secret").await
+            .expect("login against the fake server failed");
+
+        assert_eq!(token.access_token, FAKE_ACCESS_TOKEN);
+        assert_eq!(token.refresh_token, FAKE_REFRESH_TOKEN);
+    }
+
+    #[tokio::test]
+    async fn test_login_rejected() {
+        let api_root = start_server();
+
+        let result = TeslaClient::authenticate_using_api_root(api_root.as_str(), "driver@example.com", "").await;
+
+        assert!(matches!(result, Err(TeslaError::AuthError)));
+    }
+
+    #[tokio::test]
+    async fn test_login_with_mfa() {
+        let api_root = start_server();
+        let config = AuthConfig::new(&AuthRegion::from_api_root(api_root.as_str()).unwrap());
+
+        let result = TeslaClient::authenticate_with_config(&config, FAKE_MFA_EMAIL, "secret").await;
+        assert!(matches!(result, Err(TeslaError::MfaRequired)));
+
+        let mfa = match TeslaClient::login(&config, FAKE_MFA_EMAIL, "secret").await.unwrap() {
+            LoginStep::Challenge(LoginChallenge::Mfa(mfa)) => mfa,
+            step => panic!("expected a multi-factor challenge, got {:?}", step),
+        };
+        assert_eq!(mfa.factors().len(), 1);
+        assert_eq!(mfa.factors()[0].id, FAKE_MFA_FACTOR_ID);
+
+        let result = mfa.submit_passcode(FAKE_MFA_FACTOR_ID, "000000").await;
+        assert!(matches!(result, Err(TeslaError::MfaInvalidPasscode)));
+
+        match mfa.submit_passcode(FAKE_MFA_FACTOR_ID, FAKE_MFA_PASSCODE).await.unwrap() {
+            LoginStep::Token(token) => assert_eq!(token.access_token, FAKE_ACCESS_TOKEN),
+            step => panic!("expected a token, got {:?}", step),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_with_captcha() {
+        let api_root = start_server();
+        let config = AuthConfig::new(&AuthRegion::from_api_root(api_root.as_str()).unwrap());
+
+        let result = TeslaClient::authenticate_with_config(&config, FAKE_CAPTCHA_EMAIL, "secret").await;
+        assert!(matches!(result, Err(TeslaError::CaptchaRequired)));
+
+        let captcha = match TeslaClient::login(&config, FAKE_CAPTCHA_EMAIL, "secret").await.unwrap() {
+            LoginStep::Challenge(LoginChallenge::Captcha(captcha)) => captcha,
+            step => panic!("expected a captcha challenge, got {:?}", step),
+        };
+        assert_eq!(captcha.image(), FAKE_CAPTCHA_IMAGE.as_bytes());
+        assert_eq!(captcha.content_type(), Some("image/svg+xml"));
+
+        // a wrong answer is met with a new captcha
+        let captcha = match captcha.submit_answer("wrong").await.unwrap() {
+            LoginStep::Challenge(LoginChallenge::Captcha(captcha)) => captcha,
+            step => panic!("expected a new captcha challenge, got {:?}", step),
+        };
+
+        match captcha.submit_answer(FAKE_CAPTCHA_ANSWER).await.unwrap() {
+            LoginStep::Token(token) => assert_eq!(token.access_token, FAKE_ACCESS_TOKEN),
+            step => panic!("expected a token, got {:?}", step),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_browser_login() {
+        let api_root = start_server();
+        let config = AuthConfig::new(&AuthRegion::from_api_root(api_root.as_str()).unwrap());
+
+        let login = BrowserLogin::new(&config);
+        let state = login.authorize_url().query_pairs().find(|q| q.0 == "state").unwrap().1.to_string();
+
+        let forged = format!("{}?code={}&state=forged", config.redirect_uri, FAKE_AUTHORIZATION_CODE);
+        assert!(matches!(login.exchange_callback(forged.as_str()).await, Err(TeslaError::AuthError)));
+
+        let callback = format!("{}?code={}&state={}", config.redirect_uri, FAKE_AUTHORIZATION_CODE, state);
+        let token = login.exchange_callback(callback.as_str()).await.expect("callback exchange failed");
+        assert_eq!(token.access_token, FAKE_ACCESS_TOKEN);
+    }
+
+    #[tokio::test]
+    async fn test_api_errors() {
+        let api_root = start_server();
+        let client = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).with_retry_policy(RetryPolicy::none());
+
+        let error = client.vehicle(FAKE_ASLEEP_VEHICLE_ID).get().await.unwrap_err();
+        assert!(matches!(&error, TeslaError::VehicleUnavailable(e) if e.message.as_deref().unwrap().starts_with("vehicle unavailable")));
+        assert!(error.is_retryable());
+
+        let error = client.vehicle(FAKE_RATE_LIMITED_VEHICLE_ID).flash_lights().await.unwrap_err();
+        assert!(matches!(error, TeslaError::RateLimited { .. }));
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(FAKE_RETRY_AFTER_SECONDS)));
+
+        let error = client.vehicle(FAKE_BROKEN_VEHICLE_ID).get_all_data().await.unwrap_err();
+        assert!(matches!(&error, TeslaError::ServerError(e) if e.body == "upstream internal error"));
+        assert!(error.is_retryable());
+
+        let error = client.vehicle(FAKE_GARBLED_VEHICLE_ID).get().await.unwrap_err();
+        assert!(matches!(&error, TeslaError::Deserialize { payload, .. } if payload.contains("not a number")));
+        assert!(!error.is_retryable());
+
+        let error = client.vehicle(1).get_gui_settings().await.unwrap_err();
+        assert!(matches!(error, TeslaError::NotFound(_)));
+        assert!(!error.is_retryable() && !error.is_auth_error());
+
+        let error = TeslaClient::new(api_root.as_str(), FAKE_EXPIRED_TOKEN).get_vehicles().await.unwrap_err();
+        assert!(error.is_auth_error());
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let api_root = start_server();
+        let policy = RetryPolicy::new(2).initial_backoff(Duration::from_millis(10));
+        let client = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).with_retry_policy(policy);
+        let vehicle = client.vehicle(FAKE_FLAKY_VEHICLE_ID);
+
+        // every other request fails, a second attempt always goes through
+        for _ in 0..3 {
+            assert_eq!(vehicle.get().await.unwrap().display_name, "Test CAR");
+            assert!(vehicle.door_lock().await.is_ok());
+        }
+
+        // honking twice is not harmless, it is never retried
+        let first = vehicle.honk_horn().await;
+        let second = vehicle.honk_horn().await;
+        assert!(first.is_err() != second.is_err());
+
+        // neither is sharing a destination, renaming the vehicle or sending a PIN
+        let pin = Pin::new("1234").unwrap();
+        let first = vehicle.navigation_sc_request(42, 1).await;
+        let second = vehicle.navigation_sc_request(42, 1).await;
+        assert!(first.is_err() != second.is_err());
+        let first = vehicle.set_vehicle_name("Flaky CAR").await;
+        let second = vehicle.set_vehicle_name("Flaky CAR").await;
+        assert!(first.is_err() != second.is_err());
+        let first = vehicle.set_pin_to_drive(true, &pin).await;
+        let second = vehicle.set_pin_to_drive(true, &pin).await;
+        assert!(first.is_err() != second.is_err());
+
+        let error = client.vehicle(FAKE_BROKEN_VEHICLE_ID).get().await.unwrap_err();
+        assert!(matches!(error, TeslaError::ServerError(_)));
+    }
+
+    #[tokio::test]
+    async fn test_strict_commands() {
+        let api_root = start_server();
+
+        let lenient = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).vehicle(0);
+        let response = lenient.door_lock().await.unwrap();
+        assert!(!response.result);
+        assert_eq!(response.reason, "already_set");
+
+        let strict = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).strict().vehicle(0);
+        assert!(strict.door_unlock().await.unwrap().result);
+        let error = strict.door_lock().await.unwrap_err();
+        assert!(matches!(error, TeslaError::CommandRejected(RejectionReason::AlreadySet)));
+        assert!(!error.is_retryable());
+    }
+
+    type CommandFuture<'a> = std::pin::Pin<Box<dyn std::future::Future<Output = Result<SimpleResponse, TeslaError>> + 'a>>;
+
+    fn boxed<'a>(command: impl std::future::Future<Output = Result<SimpleResponse, TeslaError>> + 'a) -> CommandFuture<'a> {
+        Box::pin(command)
+    }
+
+    #[tokio::test]
+    async fn test_vehicle_commands() {
+        let (api_root, received) = start_recording_server();
+        let vehicle = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).strict().vehicle(0);
+        let pin = Pin::new("1234").unwrap();
+        let departure = ScheduledDeparture::new(TimeOfDay::new(7, 45).unwrap())
+            .preconditioning(true)
+            .off_peak_charging(TimeOfDay::new(6, 0).unwrap(), false);
+
+        // each command, with the name and the body the vehicle must receive
+        let commands = vec![
+            (boxed(vehicle.charge_start()), "charge_start", json!(null)),
+            (boxed(vehicle.charge_stop()), "charge_stop", json!(null)),
+            (boxed(vehicle.charge_max_range()), "charge_max_range", json!(null)),
+            (boxed(vehicle.charge_standard()), "charge_standard", json!(null)),
+            (boxed(vehicle.set_charge_limit(80)), "set_charge_limit", json!({ "percent": 80 })),
+            (boxed(vehicle.set_charging_amps(16)), "set_charging_amps", json!({ "charging_amps": 16 })),
+            (boxed(vehicle.set_scheduled_charging(true, "23:30".parse().unwrap())), "set_scheduled_charging",
+             json!({ "enable": true, "time": 1410 })),
+            (boxed(vehicle.set_scheduled_departure(true, &departure)), "set_scheduled_departure", json!({
+                "enable": true, "departure_time": 465, "end_off_peak_time": 360,
+                "preconditioning_enabled": true, "preconditioning_weekdays_only": true,
+                "off_peak_charging_enabled": true, "off_peak_charging_weekdays_only": false,
+            })),
+            (boxed(vehicle.set_temps(21.5, 20.0)), "set_temps", json!({ "driver_temp": 21.5, "passenger_temp": 20.0 })),
+            (boxed(vehicle.remote_seat_heater_request(Seat::RearCenter, SeatLevel::High)), "remote_seat_heater_request",
+             json!({ "heater": 4, "level": 3 })),
+            (boxed(vehicle.remote_seat_cooler_request(Seat::FrontRight, SeatLevel::Low)), "remote_seat_cooler_request",
+             json!({ "seat_position": 2, "seat_cooler_level": 1 })),
+            (boxed(vehicle.remote_steering_wheel_heater_request(true)), "remote_steering_wheel_heater_request", json!({ "on": true })),
+            (boxed(vehicle.set_preconditioning_max(true)), "set_preconditioning_max", json!({ "on": true })),
+            (boxed(vehicle.set_climate_keeper_mode(ClimateKeeperMode::Dog)), "set_climate_keeper_mode", json!({ "climate_keeper_mode": 2 })),
+            (boxed(vehicle.set_cabin_overheat_protection(true, true)), "set_cabin_overheat_protection",
+             json!({ "on": true, "fan_only": true })),
+            (boxed(vehicle.set_bioweapon_mode(false)), "set_bioweapon_mode", json!({ "on": false, "manual_override": true })),
+            (boxed(vehicle.actuate_trunk(Trunk::Front)), "actuate_trunk", json!({ "which_trunk": "front" })),
+            (boxed(vehicle.window_control(WindowCommand::Close, 37.4, -122.1)), "window_control",
+             json!({ "command": "close", "lat": 37.4, "lon": -122.1 })),
+            (boxed(vehicle.sun_roof_control("vent".parse().unwrap())), "sun_roof_control", json!({ "state": "vent" })),
+            (boxed(vehicle.set_sentry_mode(true)), "set_sentry_mode", json!({ "on": true })),
+            (boxed(vehicle.set_valet_mode(true, Some(&pin))), "set_valet_mode", json!({ "on": true, "password": "1234" })),
+            (boxed(vehicle.set_valet_mode(false, None)), "set_valet_mode", json!({ "on": false, "password": null })),
+            (boxed(vehicle.reset_valet_pin()), "reset_valet_pin", json!(null)),
+            (boxed(vehicle.speed_limit_activate(&pin)), "speed_limit_activate", json!({ "pin": "1234" })),
+            (boxed(vehicle.speed_limit_deactivate(&pin)), "speed_limit_deactivate", json!({ "pin": "1234" })),
+            (boxed(vehicle.speed_limit_set_limit(65)), "speed_limit_set_limit", json!({ "limit_mph": 65 })),
+            (boxed(vehicle.speed_limit_clear_pin(&pin)), "speed_limit_clear_pin", json!({ "pin": "1234" })),
+            (boxed(vehicle.set_pin_to_drive(true, &pin)), "set_pin_to_drive", json!({ "on": true, "password": "1234" })),
+            (boxed(vehicle.media_toggle_playback()), "media_toggle_playback", json!(null)),
+            (boxed(vehicle.media_next_track()), "media_next_track", json!(null)),
+            (boxed(vehicle.media_prev_track()), "media_prev_track", json!(null)),
+            (boxed(vehicle.media_next_fav()), "media_next_fav", json!(null)),
+            (boxed(vehicle.media_prev_fav()), "media_prev_fav", json!(null)),
+            (boxed(vehicle.media_volume_up()), "media_volume_up", json!(null)),
+            (boxed(vehicle.media_volume_down()), "media_volume_down", json!(null)),
+            (boxed(vehicle.adjust_volume(5.5)), "adjust_volume", json!({ "volume": 5.5 })),
+            (boxed(vehicle.navigation_sc_request(42, 1)), "navigation_sc_request", json!({ "id": 42, "order": 1 })),
+            (boxed(vehicle.honk_horn()), "honk_horn", json!(null)),
+            (boxed(vehicle.remote_boombox()), "remote_boombox", json!(null)),
+            (boxed(vehicle.trigger_homelink(37.4, -122.1)), "trigger_homelink", json!({ "lat": 37.4, "lon": -122.1 })),
+            (boxed(vehicle.guest_mode(true)), "guest_mode", json!({ "enable": true })),
+            (boxed(vehicle.schedule_software_update(Duration::from_secs(3600))), "schedule_software_update",
+             json!({ "offset_sec": 3600 })),
+            (boxed(vehicle.cancel_software_update()), "cancel_software_update", json!(null)),
+        ];
+
+        for (command, name, params) in commands {
+            command.await.unwrap_or_else(|e| panic!("{} failed: {:?}", name, e));
+            let last = received.lock().unwrap().pop().unwrap();
+            assert_eq!((last.name.as_str(), &last.params), (name, &params));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_command_arguments() {
+        let (api_root, received) = start_recording_server();
+        let vehicle = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).strict().vehicle(0);
+
+        let commands = vec![
+            boxed(vehicle.set_charge_limit(49)),
+            boxed(vehicle.set_charge_limit(101)),
+            boxed(vehicle.set_charging_amps(0)),
+            boxed(vehicle.set_charging_amps(49)),
+            boxed(vehicle.set_temps(21.0, 35.0)),
+            boxed(vehicle.set_temps(f64::NAN, 20.0)),
+            boxed(vehicle.remote_seat_cooler_request(Seat::RearLeft, SeatLevel::Low)),
+            boxed(vehicle.window_control(WindowCommand::Close, 91.0, 0.0)),
+            boxed(vehicle.speed_limit_set_limit(120)),
+            boxed(vehicle.adjust_volume(12.0)),
+            boxed(vehicle.trigger_homelink(-100.0, 0.0)),
+            boxed(vehicle.set_vehicle_name(" ")),
+            boxed(vehicle.share(&Destination::Location { lat: 0.0, lon: 200.0 }, "en-US")),
+        ];
+
+        for command in commands {
+            assert!(matches!(command.await, Err(TeslaError::InvalidArgument(_))));
+        }
+        // nothing reached the vehicle
+        assert!(received.lock().unwrap().is_empty());
+        assert!("side".parse::<Trunk>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rename_vehicle() {
+        let api_root = start_server();
+        let client = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).strict();
+        let vehicle = client.get_vehicle_by_name("test car").await.unwrap().unwrap();
+        let vehicle = client.vehicle(vehicle.id);
+
+        // the vehicle is only found by its new name
+        assert!(vehicle.set_vehicle_name("Renamed CAR").await.is_ok());
+        assert_eq!(client.get_vehicle_by_name("Renamed CAR").await.unwrap().unwrap().display_name, "Renamed CAR");
+        assert!(client.get_vehicle_by_name("Test CAR").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_remote_start_drive() {
+        let (api_root, received) = start_recording_server();
+        let vehicle = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).strict().vehicle(0);
+        let password = Password::new("s3cr3t pass".to_owned());
+
+        assert!(vehicle.remote_start_drive(&password).await.is_ok());
+        let last = received.lock().unwrap().pop().unwrap();
+        assert_eq!(last.name, "remote_start_drive");
+        assert_eq!(last.query.get("password").map(String::as_str), Some("s3cr3t pass"));
+        assert!(matches!(vehicle.remote_start_drive(&Password::new(String::new())).await,
+            Err(TeslaError::CommandRejected(RejectionReason::Other(_)))));
+
+        // neither the password nor a failed request, whose URL holds it, show it
+        assert!(!format!("{:?}", password).contains("s3cr3t"));
+        let unreachable = TeslaClient::new("http://127.0.0.1:1/api/1/", FAKE_ACCESS_TOKEN).vehicle(0);
+        let error = unreachable.remote_start_drive(&password).await.unwrap_err();
+        assert!(!format!("{:?} {}", error, error).contains("s3cr3t"));
+    }
+
+    #[tokio::test]
+    async fn test_software_update() {
+        let api_root = start_server();
+        let vehicle = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).strict().vehicle(0);
+
+        let update = vehicle.get_all_data().await.unwrap().vehicle_state.software_update.unwrap();
+        assert_eq!(update.status, SoftwareUpdateStatus::Available);
+        assert_eq!(update.expected_duration(), Duration::from_secs(45 * 60));
+
+        let parse = |status: &str| serde_json::from_str::<SoftwareUpdateStatus>(status).unwrap();
+        assert_eq!(parse("\"\""), SoftwareUpdateStatus::None);
+        assert_eq!(parse("\"downloading_wifi_wait\""), SoftwareUpdateStatus::DownloadingWifiWait);
+        assert_eq!(parse("\"rebooting\""), SoftwareUpdateStatus::Unknown);
+    }
+
+    #[test]
+    fn test_software_update_older_firmware() {
+        let mut vehicle_state = serde_json::to_value(fake_vehicle_data().vehicle_state).unwrap();
+        vehicle_state["software_update"] = serde_json::json!({ "expected_duration_sec": 2700, "status": "" });
+
+        let vehicle_state: VehicleState = serde_json::from_value(vehicle_state).unwrap();
+        let update = vehicle_state.software_update.unwrap();
+        assert_eq!(update.status, SoftwareUpdateStatus::None);
+        assert_eq!((update.version.as_str(), update.download_perc, update.install_perc), ("", 0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_data_requests() {
+        let api_root = start_server();
+        let vehicle = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).vehicle(0);
+
+        assert_eq!(vehicle.get_soc().await.unwrap().battery_level, 50);
+        assert_eq!(vehicle.get_climate_state().await.unwrap().outside_temp, 10.0);
+        assert_eq!(vehicle.get_drive_state().await.unwrap().shift_state, None);
+        let charge_state = vehicle.get_soc().await.unwrap();
+        assert_eq!(charge_state.scheduled_charging_mode.as_deref(), Some("StartAt"));
+        assert_eq!(charge_state.scheduled_charging_start_time_app, Some(TimeOfDay::new(23, 30).unwrap()));
+        assert_eq!(charge_state.off_peak_hours_end_time.map(|t| t.to_string()).as_deref(), Some("06:00"));
+
+        let media = vehicle.get_vehicle_state().await.unwrap().media_info.unwrap();
+        assert_eq!(media.now_playing_title.as_deref(), Some("Fake song"));
+        assert_eq!(media.audio_volume, Some(4.5));
+        assert_eq!(vehicle.get_vehicle_config().await.unwrap().car_type, "model3");
+        assert!(vehicle.get_mobile_enabled().await.unwrap());
+        assert_eq!(vehicle.get_service_data().await.unwrap().service_status, "not_in_service");
+
+        let sites = vehicle.nearby_charging_sites().await.unwrap();
+        assert_eq!(sites.superchargers.len(), 2);
+        assert_eq!(sites.destination_charging.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_nearby_charging_sites() {
+        let api_root = start_server();
+        let vehicle = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).vehicle(0);
+
+        let sites = vehicle.nearby_charging_sites().await.unwrap();
+        let nearest = &sites.superchargers[0];
+        assert_eq!(nearest.name, "Mountain View, CA");
+        assert_eq!(nearest.location, Location { lat: 37.3948, lon: -122.0779 });
+        assert_eq!((nearest.available_stalls, nearest.total_stalls, nearest.site_closed), (0, 8, false));
+        assert_eq!(sites.superchargers[1].available_stalls, 3);
+
+        assert_eq!(sites.destination_charging[0].name, "Palo Alto, CA - Garage");
+        assert_eq!(sites.destination_charging[0].amenities, None);
+        assert_eq!(sites.destination_charging[1].amenities.as_deref(), Some("restrooms,wifi"));
+    }
+
+    #[tokio::test]
+    async fn test_products() {
+        let api_root = start_server();
+        let products = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).get_products().await.unwrap();
+
+        assert_eq!(products.len(), 3);
+        assert!(matches!(&products[0], Product::Vehicle(v) if v.display_name == "Test CAR"));
+        assert!(matches!(&products[1], Product::EnergySite(s) if s.energy_site_id == FAKE_ENERGY_SITE_ID));
+        assert!(matches!(&products[2], Product::Other(_)));
+    }
+
+    #[tokio::test]
+    async fn test_energy_site() {
+        let api_root = start_server();
+        let site = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).strict().energy_site(FAKE_ENERGY_SITE_ID);
+
+        assert_eq!(site.site_status().await.unwrap().battery_type.as_deref(), Some("ac_powerwall"));
+
+        let live = site.live_status().await.unwrap();
+        assert_eq!((live.solar_power, live.battery_power, live.grid_power, live.load_power), (3620.0, -1250.0, -500.0, 1870.0));
+
+        let info = site.site_info().await.unwrap();
+        assert_eq!(info.operation_mode(), Some(OperationMode::SelfConsumption));
+        assert_eq!(info.user_settings.and_then(|s| s.storm_mode_enabled), Some(true));
+
+        let history = site.calendar_history(HistoryPeriod::Day).await.unwrap();
+        assert_eq!(history.time_series[0].solar_energy_exported, 18420.0);
+        assert_eq!(history.time_series[0].battery_energy_imported_from_grid, 0.0);
+
+        assert_eq!(site.backup_reserve(30).await.unwrap().code, 201);
+        assert!(site.operation_mode("backup".parse().unwrap()).await.is_ok());
+        assert!(site.storm_mode(true).await.is_ok());
+        assert!(matches!(site.backup_reserve(120).await, Err(TeslaError::InvalidArgument(_))));
+    }
+
+    #[tokio::test]
+    async fn test_navigation() {
+        let (api_root, received) = start_recording_server();
+        let vehicle = TeslaClient::new(api_root.as_str(), FAKE_ACCESS_TOKEN).strict().vehicle(0);
+
+        let destinations = [
+            (Destination::Address("3500 Deer Creek Road, Palo Alto".to_owned()), "3500 Deer Creek Road, Palo Alto"),
+            (Destination::Location { lat: 37.4, lon: -122.1 }, "37.4,-122.1"),
+        ];
+        for (destination, text) in destinations.iter() {
+            assert!(vehicle.share(destination, "en-US").await.is_ok());
+
+            let last = received.lock().unwrap().pop().unwrap();
+            assert_eq!(last.name, "navigation_request");
+            assert_eq!(last.params["type"], "share_ext_content_raw");
+            assert_eq!(last.params["value"]["android.intent.extra.TEXT"], *text);
+            assert_eq!(last.params["locale"], "en-US");
+            assert!(last.params["timestamp_ms"].as_str().unwrap().parse::<u64>().is_ok());
+        }
+        assert_eq!(Destination::Location { lat: 37.5, lon: -122.25 }.text(), "37.5,-122.25");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token() {
+        let api_root = start_server();
+        let region: AuthRegion = api_root.replace("api/1/", "").parse().unwrap();
+
+        let token = TeslaClient::refresh_token_with_config(&AuthConfig::new(&region), FAKE_REFRESH_TOKEN).await
+            .expect("refresh against the fake server failed");
+        assert_eq!(token.access_token, FAKE_ACCESS_TOKEN);
+
+        let result = TeslaClient::refresh_token_with_region(&region, "unknown_refresh_token").await;
+        assert!(matches!(result, Err(TeslaError::AuthError)));
+    }
+
+    fn refreshing_client(api_root: &str, expires_at: SystemTime) -> (TeslaClient, Arc<Mutex<Vec<String>>>) {
+        let config = AuthConfig::new(&AuthRegion::from_api_root(api_root).unwrap());
+        let refreshed = Arc::new(Mutex::new(Vec::new()));
+        let saved = refreshed.clone();
+        let provider = RefreshingToken::new(config, FAKE_EXPIRED_TOKEN, FAKE_REFRESH_TOKEN, expires_at)
+            .on_refresh(move |token| saved.lock().unwrap().push(token.access_token.clone()));
+
+        (TeslaClient::with_token_provider(api_root, provider), refreshed)
+    }
+
+    struct ReadOnlyStore;
+
+    impl TokenStore for ReadOnlyStore {
+        fn load(&self) -> Result<Option<StoredToken>, TeslaError> {
+            Ok(Some(StoredToken {
+                access_token: FAKE_EXPIRED_TOKEN.to_string(),
+                refresh_token: FAKE_REFRESH_TOKEN.to_string(),
+                expires_at: 1,
+            }))
+        }
+
+        fn save(&self, _token: &StoredToken) -> Result<(), TeslaError> {
+            Err(TeslaError::IoError(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "read-only")))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_not_saved() {
+        let api_root = start_server();
+        let config = AuthConfig::new(&AuthRegion::from_api_root(api_root.as_str()).unwrap());
+        let provider = RefreshingToken::from_store(config, ReadOnlyStore).unwrap();
+        let client = TeslaClient::with_token_provider(api_root.as_str(), provider);
+
+        let error = client.get_vehicles().await.unwrap_err();
+        assert!(matches!(&error, TeslaError::ParseAppError(e) if e.to_string().contains("read-only")));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_on_invalid_token() {
+        let api_root = start_server();
+        let (client, refreshed) = refreshing_client(api_root.as_str(), SystemTime::now() + Duration::from_secs(3600));
+
+        let result = TeslaClient::new(api_root.as_str(), FAKE_EXPIRED_TOKEN).get_vehicles().await;
+        assert!(matches!(result, Err(TeslaError::InvalidTokenError)));
+
+        assert_eq!(client.get_vehicles().await.unwrap().len(), 1);
+        assert_eq!(client.get_vehicles().await.unwrap().len(), 1);
+        assert_eq!(*refreshed.lock().unwrap(), vec![FAKE_ACCESS_TOKEN.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_before_expiry() {
+        let api_root = start_server();
+        let (client, refreshed) = refreshing_client(api_root.as_str(), SystemTime::now());
+
+        assert_eq!(client.get_vehicles().await.unwrap().len(), 1);
+        assert_eq!(*refreshed.lock().unwrap(), vec![FAKE_ACCESS_TOKEN.to_string()]);
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+use std::str::FromStr;
+
+use reqwest::Url;
+
+use crate::claims::AccessTokenClaims;
+use crate::tesla_rs_error::{AppError, TeslaError};
+
+const GLOBAL_AUTH_BASE_URI: &str = "https://auth.tesla.com/";
+const CHINA_AUTH_BASE_URI: &str = "https://auth.tesla.cn/";
+
+const ENDPOINT_AUTHORIZE: &str = "oauth2/v3/authorize";
+const ENDPOINT_TOKEN: &str = "oauth2/v3/token";
+const ENDPOINT_CALLBACK: &str = "void/callback";
+
+// Tesla only accepts its own callback as redirect uri, for both the global and the China SSO
+const TESLA_REDIRECT_URI: &str = "https://auth.tesla.com/void/callback";
+
+/// The Tesla SSO instance an account is registered with.
+///
+/// Accounts created on the China site can only log in against `auth.tesla.cn`,
+/// every other account uses `auth.tesla.com`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum AuthRegion {
+    #[default]
+    Global,
+    China,
+    /// Any other server implementing the Tesla SSO routes, given by its base URL
+    Custom(Url),
+}
+
+/// The endpoints used by the login and refresh flows.
+///
+/// Built from an [`AuthRegion`], every URL can then be replaced, ex: to run the flow against a test server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthConfig {
+    pub authorize_url: Url,
+    pub token_url: Url,
+    pub redirect_uri: Url,
+}
+
+impl AuthRegion {
+    pub fn base_url(&self) -> Url {
+        match self {
+            AuthRegion::Global => Url::parse(GLOBAL_AUTH_BASE_URI).expect("cannot parse auth base url"),
+            AuthRegion::China => Url::parse(CHINA_AUTH_BASE_URI).expect("cannot parse auth base url"),
+            AuthRegion::Custom(url) => url.clone(),
+        }
+    }
+
+    pub fn authorize_url(&self) -> Url {
+        self.base_url().join(ENDPOINT_AUTHORIZE).expect("cannot parse authorize endpoint")
+    }
+
+    pub fn token_url(&self) -> Url {
+        self.base_url().join(ENDPOINT_TOKEN).expect("cannot parse token endpoint")
+    }
+
+    pub fn redirect_uri(&self) -> Url {
+        match self {
+            AuthRegion::Custom(url) => url.join(ENDPOINT_CALLBACK).expect("cannot parse callback endpoint"),
+            _ => Url::parse(TESLA_REDIRECT_URI).expect("cannot parse redirect uri"),
+        }
+    }
+
+    /// The SSO routes served by the host of an owner API root, ex: `http://localhost:4321/api/1/`.
+    pub fn from_api_root(api_root: &str) -> Result<AuthRegion, TeslaError> {
+        let auth_root = Url::parse(api_root).and_then(|u| u.join("/")).map_err(|_| AppError {
+            message: format!("Could not parse API root {}", api_root)
+        })?;
+        Ok(AuthRegion::Custom(auth_root))
+    }
+
+    /// Maps a token issuer (the `iss` claim, ex: `https://auth.tesla.cn/oauth2/v3`) to its region.
+    ///
+    /// Only the Tesla SSOs are recognized, a custom SSO has to be configured explicitly.
+    pub fn from_issuer(issuer: &str) -> Option<AuthRegion> {
+        match Url::parse(issuer).ok()?.host_str()? {
+            "auth.tesla.com" => Some(AuthRegion::Global),
+            "auth.tesla.cn" => Some(AuthRegion::China),
+            _ => None,
+        }
+    }
+
+    /// Detects the region which issued a token by reading the issuer of the JWT payload.
+    ///
+    /// The signature is not checked, which is why any other issuer than the Tesla SSOs is ignored:
+    /// a crafted token must not send the refresh token to the host it names.
+    pub fn from_token(token: &str) -> Option<AuthRegion> {
+        AccessTokenClaims::decode(token).ok()?.region()
+    }
+}
+
+impl AuthConfig {
+    pub fn new(region: &AuthRegion) -> AuthConfig {
+        AuthConfig {
+            authorize_url: region.authorize_url(),
+            token_url: region.token_url(),
+            redirect_uri: region.redirect_uri(),
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig::new(&AuthRegion::default())
+    }
+}
+
+impl From<AuthRegion> for AuthConfig {
+    fn from(region: AuthRegion) -> Self {
+        AuthConfig::new(&region)
+    }
+}
+
+impl fmt::Display for AuthRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthRegion::Global => write!(f, "global"),
+            AuthRegion::China => write!(f, "china"),
+            AuthRegion::Custom(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+impl FromStr for AuthRegion {
+    type Err = TeslaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "global" | "com" => Ok(AuthRegion::Global),
+            "china" | "cn" => Ok(AuthRegion::China),
+            _ => {
+                let mut url = Url::parse(s).map_err(|_| AppError {
+                    message: format!("Unknown auth region: {}", s)
+                })?;
+                if !url.path().ends_with('/') {
+                    let path = format!("{}/", url.path());
+                    url.set_path(path.as_str());
+                }
+                Ok(AuthRegion::Custom(url))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_jwt(payload: &str) -> String {
+        format!("eyJhbGciOiJSUzI1NiJ9.{}.c2lnbmF0dXJl", base64::encode_config(payload, base64::URL_SAFE_NO_PAD))
+    }
+
+    #[test]
+    fn test_region_from_token_issuer() {
+        let cn = fake_jwt(r#"{"iss":"https://auth.tesla.cn/oauth2/v3","aud":"ownerapi"}"#);
+        let com = fake_jwt(r#"{"iss":"https://auth.tesla.com/oauth2/v3"}"#);
+        let foreign = fake_jwt(r#"{"iss":"https://evil.example.com/oauth2/v3"}"#);
+
+        assert_eq!(AuthRegion::from_token(cn.as_str()), Some(AuthRegion::China));
+        assert_eq!(AuthRegion::from_token(com.as_str()), Some(AuthRegion::Global));
+        assert_eq!(AuthRegion::from_token(foreign.as_str()), None);
+        assert_eq!(AuthRegion::from_token(foreign.as_str()).unwrap_or_default(), AuthRegion::Global);
+        assert_eq!(AuthRegion::from_token("not_a_jwt"), None);
+    }
+
+    #[test]
+    fn test_region_endpoints() {
+        assert_eq!(AuthRegion::China.token_url().as_str(), "https://auth.tesla.cn/oauth2/v3/token");
+        assert_eq!(AuthRegion::Global.authorize_url().as_str(), "https://auth.tesla.com/oauth2/v3/authorize");
+
+        let custom: AuthRegion = "http://localhost:4321/sso".parse().unwrap();
+        assert_eq!(custom.token_url().as_str(), "http://localhost:4321/sso/oauth2/v3/token");
+        assert_eq!(custom.redirect_uri().as_str(), "http://localhost:4321/sso/void/callback");
+        assert_eq!(AuthRegion::China.redirect_uri().as_str(), "https://auth.tesla.com/void/callback");
+        assert_eq!("CN".parse::<AuthRegion>().unwrap(), AuthRegion::China);
+    }
+}
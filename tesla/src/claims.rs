@@ -0,0 +1,133 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::auth::AuthRegion;
+use crate::models::OAuthToken;
+use crate::tesla_rs_error::{AppError, TeslaError};
+
+/// Scopes requested at login, all of them are needed to use the owner API and refresh the token.
+pub const OWNER_API_SCOPES: [&str; 3] = ["openid", "email", "offline_access"];
+
+/// The claims of an access token, a JWT issued by the Tesla SSO.
+///
+/// The signature is not checked: the claims tell what the token is meant for,
+/// only the API can tell if it is valid.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct AccessTokenClaims {
+    #[serde(rename = "iss")]
+    pub issuer: String,
+    #[serde(rename = "sub", default)]
+    pub subject: Option<String>,
+    #[serde(rename = "aud", default, deserialize_with = "one_or_many")]
+    pub audience: Vec<String>,
+    #[serde(rename = "scp", default, deserialize_with = "one_or_many")]
+    pub scopes: Vec<String>,
+    /// Issue time, in seconds since the UNIX epoch
+    #[serde(rename = "iat", default)]
+    pub issued_at: Option<u64>,
+    /// Expiry, in seconds since the UNIX epoch
+    #[serde(rename = "exp", default)]
+    pub expires_at: Option<u64>,
+}
+
+impl AccessTokenClaims {
+    /// Decodes the payload of a JWT.
+    pub fn decode(token: &str) -> Result<AccessTokenClaims, TeslaError> {
+        let invalid = |reason: &str| AppError {
+            message: format!("Access token is not a JWT: {}", reason)
+        };
+
+        let payload = token.split('.').nth(1).ok_or_else(|| invalid("no payload"))?;
+        let decoded = base64::decode_config(payload.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+            .map_err(|e| invalid(e.to_string().as_str()))?;
+        let claims = serde_json::from_slice(&decoded).map_err(|e| invalid(e.to_string().as_str()))?;
+        Ok(claims)
+    }
+
+    pub fn expiry(&self) -> Option<SystemTime> {
+        self.expires_at.map(|exp| UNIX_EPOCH + Duration::from_secs(exp))
+    }
+
+    /// Time left before the token expires, zero once expired, `None` without expiry claim.
+    pub fn remaining_lifetime(&self) -> Option<Duration> {
+        self.expiry().map(|exp| exp.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining_lifetime() == Some(Duration::from_secs(0))
+    }
+
+    /// The region which issued the token, to refresh it against.
+    pub fn region(&self) -> Option<AuthRegion> {
+        AuthRegion::from_issuer(self.issuer.as_str())
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    /// The scopes of `expected` the token was not granted.
+    pub fn missing_scopes<'a>(&self, expected: &[&'a str]) -> Vec<&'a str> {
+        expected.iter().copied().filter(|s| !self.has_scope(s)).collect()
+    }
+}
+
+impl OAuthToken {
+    /// The claims of the access token.
+    pub fn claims(&self) -> Result<AccessTokenClaims, TeslaError> {
+        AccessTokenClaims::decode(self.access_token.as_str())
+    }
+}
+
+// `aud` and `scp` are either a single string or an array of strings
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => value.split(' ').filter(|s| !s.is_empty()).map(str::to_owned).collect(),
+        OneOrMany::Many(values) => values,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_jwt(payload: &str) -> String {
+        format!("eyJhbGciOiJSUzI1NiJ9.{}.c2lnbmF0dXJl", base64::encode_config(payload, base64::URL_SAFE_NO_PAD))
+    }
+
+    #[test]
+    fn test_decode_claims() {
+        let token = fake_jwt(r#"{"iss":"https://auth.tesla.cn/oauth2/v3/nts","sub":"user-id",
+            "aud":["https://owner-api.teslamotors.com/","https://auth.tesla.cn/oauth2/v3/userinfo"],
+            "scp":["openid","offline_access"],"iat":1600000000,"exp":1600028800}"#);
+        let claims = AccessTokenClaims::decode(token.as_str()).unwrap();
+
+        assert_eq!(claims.region(), Some(AuthRegion::China));
+        assert_eq!(claims.audience.len(), 2);
+        assert_eq!(claims.expiry(), Some(UNIX_EPOCH + Duration::from_secs(1_600_028_800)));
+        assert!(claims.is_expired());
+        assert!(claims.has_scope("offline_access"));
+        assert_eq!(claims.missing_scopes(&OWNER_API_SCOPES), vec!["email"]);
+    }
+
+    #[test]
+    fn test_decode_single_values() {
+        let token = fake_jwt(r#"{"iss":"https://auth.tesla.com/oauth2/v3","aud":"ownerapi","scp":"openid email"}"#);
+        let claims = AccessTokenClaims::decode(token.as_str()).unwrap();
+
+        assert_eq!(claims.audience, vec!["ownerapi"]);
+        assert_eq!(claims.scopes, vec!["openid", "email"]);
+        assert_eq!(claims.remaining_lifetime(), None);
+        assert!(!claims.is_expired());
+
+        assert!(AccessTokenClaims::decode("not_a_jwt").is_err());
+    }
+}
@@ -0,0 +1,372 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::tesla_rs_error::TeslaError;
+
+/// A seat with a heater, as numbered by the API.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Seat {
+    FrontLeft = 0,
+    FrontRight = 1,
+    RearLeft = 2,
+    RearCenter = 4,
+    RearRight = 5,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SeatLevel {
+    Off = 0,
+    Low = 1,
+    Medium = 2,
+    High = 3,
+}
+
+/// What the climate keeps doing once the vehicle is parked and left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClimateKeeperMode {
+    Off = 0,
+    /// Keep the cabin temperature
+    On = 1,
+    /// Keep the cabin temperature, showing on the screen that the pet inside is fine
+    Dog = 2,
+    /// Keep the cabin temperature, the screen and the outlets on
+    Camp = 3,
+}
+
+impl Seat {
+    /// Position of the seat for the cooler, only the front seats are ventilated.
+    pub fn cooler_position(self) -> Option<u8> {
+        match self {
+            Seat::FrontLeft => Some(1),
+            Seat::FrontRight => Some(2),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trunk {
+    Front,
+    Rear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowCommand {
+    /// Opens all the windows a few centimeters
+    Vent,
+    Close,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SunRoofState {
+    Vent,
+    Close,
+    /// Stops the sunroof where it is
+    Stop,
+}
+
+/// A time of the day, sent to the API as minutes after midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub struct TimeOfDay(u16);
+
+impl TimeOfDay {
+    pub fn new(hour: u8, minute: u8) -> Result<TimeOfDay, TeslaError> {
+        if hour > 23 || minute > 59 {
+            return Err(TeslaError::InvalidArgument(format!("invalid time of day: {}:{}", hour, minute)));
+        }
+        Ok(TimeOfDay(hour as u16 * 60 + minute as u16))
+    }
+
+    pub fn hour(self) -> u8 {
+        (self.0 / 60) as u8
+    }
+
+    pub fn minute(self) -> u8 {
+        (self.0 % 60) as u8
+    }
+
+    pub fn minutes_after_midnight(self) -> u16 {
+        self.0
+    }
+}
+
+impl TryFrom<u16> for TimeOfDay {
+    type Error = TeslaError;
+
+    fn try_from(minutes: u16) -> Result<Self, Self::Error> {
+        if minutes >= 24 * 60 {
+            return Err(TeslaError::InvalidArgument(format!("invalid time of day: {} minutes after midnight", minutes)));
+        }
+        Ok(TimeOfDay(minutes))
+    }
+}
+
+impl From<TimeOfDay> for u16 {
+    fn from(time: TimeOfDay) -> Self {
+        time.0
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour(), self.minute())
+    }
+}
+
+/// When the vehicle leaves, to be charged and preconditioned by then.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScheduledDeparture {
+    pub departure_time: TimeOfDay,
+    pub preconditioning: bool,
+    pub preconditioning_weekdays_only: bool,
+    pub off_peak_charging: bool,
+    pub off_peak_charging_weekdays_only: bool,
+    /// End of the off-peak hours, charging is delayed to finish by then
+    pub end_off_peak_time: TimeOfDay,
+}
+
+impl ScheduledDeparture {
+    /// Departure at `departure_time`, without preconditioning nor off-peak charging.
+    pub fn new(departure_time: TimeOfDay) -> ScheduledDeparture {
+        ScheduledDeparture {
+            departure_time,
+            preconditioning: false,
+            preconditioning_weekdays_only: false,
+            off_peak_charging: false,
+            off_peak_charging_weekdays_only: false,
+            end_off_peak_time: departure_time,
+        }
+    }
+
+    /// Warms up or cools down the cabin and the battery for the departure.
+    pub fn preconditioning(mut self, weekdays_only: bool) -> ScheduledDeparture {
+        self.preconditioning = true;
+        self.preconditioning_weekdays_only = weekdays_only;
+        self
+    }
+
+    /// Charges during the off-peak hours, ending at `end_off_peak_time`.
+    pub fn off_peak_charging(mut self, end_off_peak_time: TimeOfDay, weekdays_only: bool) -> ScheduledDeparture {
+        self.off_peak_charging = true;
+        self.off_peak_charging_weekdays_only = weekdays_only;
+        self.end_off_peak_time = end_off_peak_time;
+        self
+    }
+}
+
+/// How a Powerwall uses its battery.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationMode {
+    /// Stores the solar production to use it at night
+    SelfConsumption,
+    /// Keeps the battery full for a grid outage
+    Backup,
+    /// Charges and discharges following the utility rates
+    Autonomous,
+}
+
+/// Period of the energy history, the current day, week...
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HistoryPeriod {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl HistoryPeriod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HistoryPeriod::Day => "day",
+            HistoryPeriod::Week => "week",
+            HistoryPeriod::Month => "month",
+            HistoryPeriod::Year => "year",
+        }
+    }
+}
+
+/// Where to navigate to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Destination {
+    /// Any text the navigation can search, ex: an address or the name of a place
+    Address(String),
+    Location { lat: f64, lon: f64 },
+}
+
+impl Destination {
+    /// The text shared with the vehicle, as if shared from the phone app.
+    pub fn text(&self) -> String {
+        match self {
+            Destination::Address(address) => address.clone(),
+            Destination::Location { lat, lon } => format!("{},{}", lat, lon),
+        }
+    }
+}
+
+/// A 4 digits PIN, for the valet mode, the speed limit or PIN to drive.
+///
+/// It is hidden from `Debug`, so it never ends up in a log, and wiped from memory once dropped.
+#[derive(Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Pin(String);
+
+impl Pin {
+    pub fn new(pin: &str) -> Result<Pin, TeslaError> {
+        if pin.len() != 4 || !pin.chars().all(|c| c.is_ascii_digit()) {
+            return Err(TeslaError::InvalidArgument("PIN must be 4 digits".to_owned()));
+        }
+        Ok(Pin(pin.to_owned()))
+    }
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Pin(****)")
+    }
+}
+
+/// The password of the Tesla account, needed to start the vehicle without its key.
+///
+/// It is hidden from `Debug` and wiped from memory once dropped.
+pub struct Password(String);
+
+impl Password {
+    pub fn new(password: String) -> Password {
+        Password(password)
+    }
+
+    pub(crate) fn expose(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl From<String> for Password {
+    fn from(password: String) -> Self {
+        Password::new(password)
+    }
+}
+
+impl Drop for Password {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Password(****)")
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = TeslaError;
+
+    /// Parses "HH:MM", ex: "23:30".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || TeslaError::InvalidArgument(format!("invalid time of day: {}", s));
+        let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+        TimeOfDay::new(hour.parse().map_err(|_| invalid())?, minute.parse().map_err(|_| invalid())?)
+    }
+}
+
+impl FromStr for OperationMode {
+    type Err = TeslaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "self_consumption" => Ok(OperationMode::SelfConsumption),
+            "backup" => Ok(OperationMode::Backup),
+            "autonomous" => Ok(OperationMode::Autonomous),
+            _ => Err(TeslaError::InvalidArgument(format!("unknown operation mode: {}", s))),
+        }
+    }
+}
+
+impl FromStr for Trunk {
+    type Err = TeslaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "front" | "frunk" => Ok(Trunk::Front),
+            "rear" => Ok(Trunk::Rear),
+            _ => Err(TeslaError::InvalidArgument(format!("unknown trunk: {}", s))),
+        }
+    }
+}
+
+impl FromStr for WindowCommand {
+    type Err = TeslaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "vent" => Ok(WindowCommand::Vent),
+            "close" => Ok(WindowCommand::Close),
+            _ => Err(TeslaError::InvalidArgument(format!("unknown window command: {}", s))),
+        }
+    }
+}
+
+impl FromStr for SunRoofState {
+    type Err = TeslaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "vent" => Ok(SunRoofState::Vent),
+            "close" => Ok(SunRoofState::Close),
+            "stop" => Ok(SunRoofState::Stop),
+            _ => Err(TeslaError::InvalidArgument(format!("unknown sunroof state: {}", s))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin() {
+        let pin = Pin::new("0123").unwrap();
+        assert_eq!(format!("{:?}", pin), "Pin(****)");
+        assert_eq!(serde_json::to_string(&pin).unwrap(), "\"0123\"");
+
+        assert!(Pin::new("123").is_err());
+        assert!(Pin::new("12a4").is_err());
+        assert!(Pin::new("١٢٣٤").is_err());
+    }
+
+    #[test]
+    fn test_time_of_day() {
+        let time: TimeOfDay = "23:05".parse().unwrap();
+        assert_eq!(time, TimeOfDay::new(23, 5).unwrap());
+        assert_eq!(time.minutes_after_midnight(), 1385);
+        assert_eq!(time.to_string(), "23:05");
+        assert_eq!(serde_json::to_string(&time).unwrap(), "1385");
+        assert_eq!(serde_json::from_str::<TimeOfDay>("1385").unwrap(), time);
+
+        assert!(serde_json::from_str::<TimeOfDay>("1440").is_err());
+        assert!(TimeOfDay::new(24, 0).is_err());
+        assert!("7h30".parse::<TimeOfDay>().is_err());
+    }
+
+    #[test]
+    fn test_password_debug() {
+        let password = Password::from("hunter2".to_owned());
+        assert_eq!(format!("{:?}", password), "Password(****)");
+        assert_eq!(password.expose(), "hunter2");
+    }
+}
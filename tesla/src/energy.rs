@@ -0,0 +1,97 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+use crate::commands::{HistoryPeriod, OperationMode};
+use crate::models::{CalendarHistory, EnergySiteCommandResponse, LiveStatus, Response, SiteInfo, SiteStatus};
+use crate::tesla_rs_error::{RejectionReason, TeslaError};
+use crate::TeslaClient;
+
+const ENERGY_SITE_STATUS: &str = "site_status";
+const ENERGY_SITE_LIVE_STATUS: &str = "live_status";
+const ENERGY_SITE_INFO: &str = "site_info";
+const ENERGY_SITE_CALENDAR_HISTORY: &str = "calendar_history";
+const ENERGY_SITE_COMMAND_BACKUP: &str = "backup";
+const ENERGY_SITE_COMMAND_OPERATION: &str = "operation";
+const ENERGY_SITE_COMMAND_STORM_MODE: &str = "storm_mode";
+
+/// A client for an energy site: a Powerwall, with or without solar panels.
+#[derive(Clone)]
+pub struct EnergySiteClient {
+    tesla_client: TeslaClient,
+    energy_site_id: u64,
+}
+
+impl TeslaClient {
+    pub fn energy_site(&self, energy_site_id: u64) -> EnergySiteClient {
+        EnergySiteClient {
+            tesla_client: self.clone(),
+            energy_site_id,
+        }
+    }
+}
+
+impl EnergySiteClient {
+    pub async fn site_status(&self) -> Result<SiteStatus, TeslaError> {
+        self.get_some_data(self.get_url(ENERGY_SITE_STATUS)).await
+    }
+
+    /// What the solar panels produce, and what the battery, the grid and the home draw, right now.
+    pub async fn live_status(&self) -> Result<LiveStatus, TeslaError> {
+        self.get_some_data(self.get_url(ENERGY_SITE_LIVE_STATUS)).await
+    }
+
+    pub async fn site_info(&self) -> Result<SiteInfo, TeslaError> {
+        self.get_some_data(self.get_url(ENERGY_SITE_INFO)).await
+    }
+
+    /// The energy produced, stored and consumed over the current `period`.
+    pub async fn calendar_history(&self, period: HistoryPeriod) -> Result<CalendarHistory, TeslaError> {
+        let request = self.tesla_client.client.get(self.get_url(ENERGY_SITE_CALENDAR_HISTORY))
+            .query(&[("kind", "energy"), ("period", period.as_str())]);
+        let resp: Response<CalendarHistory> = self.tesla_client.execute(request, true).await?;
+        Ok(resp.into_response())
+    }
+
+    /// Share of the battery kept for a grid outage, in percent.
+    pub async fn backup_reserve(&self, percent: u8) -> Result<EnergySiteCommandResponse, TeslaError> {
+        if percent > 100 {
+            return Err(TeslaError::InvalidArgument(format!("backup reserve must be between 0 and 100%, got {}", percent)));
+        }
+        self.post_command(ENERGY_SITE_COMMAND_BACKUP, &json!({ "backup_reserve_percent": percent })).await
+    }
+
+    pub async fn operation_mode(&self, mode: OperationMode) -> Result<EnergySiteCommandResponse, TeslaError> {
+        self.post_command(ENERGY_SITE_COMMAND_OPERATION, &json!({ "default_real_mode": mode })).await
+    }
+
+    /// Charges the battery fully when a storm warning is issued nearby.
+    pub async fn storm_mode(&self, enabled: bool) -> Result<EnergySiteCommandResponse, TeslaError> {
+        self.post_command(ENERGY_SITE_COMMAND_STORM_MODE, &json!({ "enabled": enabled })).await
+    }
+
+    async fn get_some_data<T: DeserializeOwned>(&self, url: reqwest::Url) -> Result<T, TeslaError> {
+        let resp: Response<T> = self.tesla_client.execute(self.tesla_client.client.get(url), true).await?;
+        Ok(resp.into_response())
+    }
+
+    // every energy site command only sets a value, they can all be retried
+    async fn post_command<B: Serialize>(&self, command: &str, body: &B) -> Result<EnergySiteCommandResponse, TeslaError> {
+        let request = self.tesla_client.client.post(self.get_url(command)).json(body);
+        let resp: Response<EnergySiteCommandResponse> = self.tesla_client.execute(request, true).await?;
+        let response = resp.into_response();
+
+        if self.tesla_client.strict && !(200..300).contains(&response.code) {
+            return Err(TeslaError::CommandRejected(RejectionReason::from(response.message.as_str())));
+        }
+        Ok(response)
+    }
+
+    fn get_url(&self, endpoint: &str) -> reqwest::Url {
+        let path = format!("energy_sites/{}/{}", self.energy_site_id, endpoint);
+
+        self.tesla_client.api_root
+            .join(path.as_str())
+            .unwrap()
+    }
+}
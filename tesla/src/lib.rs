@@ -1,29 +1,49 @@
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use reqwest;
-use reqwest::{Client, Url};
-use reqwest::header;
-use reqwest::redirect::Policy;
+use reqwest::{Client, RequestBuilder};
+use serde::Serialize;
 use serde::de::DeserializeOwned;
+use serde_json::json;
 
+pub use auth::*;
+pub use claims::{AccessTokenClaims, OWNER_API_SCOPES};
+pub use commands::*;
+pub use energy::EnergySiteClient;
+pub use login::{BrowserLogin, CaptchaChallenge, LoginChallenge, LoginStep, MfaChallenge, MfaFactor};
 pub use models::*;
+pub use retry::RetryPolicy;
+pub use store::{FileTokenStore, StoredToken, TokenStore};
 pub use tesla_rs_error::*;
-use rand::Rng;
-use sha2::{Sha256, Digest};
-use select::document::Document;
-use select::predicate::{Attr, Name, And};
-
-use async_recursion::async_recursion;
+pub use token::{RefreshingToken, StaticToken, TokenProvider};
 
+mod auth;
+mod claims;
+mod commands;
+mod energy;
+mod login;
 mod tesla_rs_error;
 mod models;
+mod retry;
+mod store;
+mod token;
 
-const DEFAULT_BASE_URI: &str = "https://owner-api.teslamotors.com/api/1/";
+pub const DEFAULT_BASE_URI: &str = "https://owner-api.teslamotors.com/api/1/";
 const ENDPOINT_GET_VEHICLES: &str = "vehicles";
+const ENDPOINT_GET_PRODUCTS: &str = "products";
 #[allow(dead_code)]
 const ENDPOINT_GET_VEHICLE: &str = "vehicles/{}";
 
 const VEHICLE_CHARGE_STATE: &str = "data_request/charge_state";
 const VEHICLE_GUI_SETTINGS: &str = "data_request/gui_settings";
+const VEHICLE_CLIMATE_STATE: &str = "data_request/climate_state";
+const VEHICLE_DRIVE_STATE: &str = "data_request/drive_state";
+const VEHICLE_STATE: &str = "data_request/vehicle_state";
+const VEHICLE_CONFIG: &str = "data_request/vehicle_config";
+const VEHICLE_MOBILE_ENABLED: &str = "mobile_enabled";
+const VEHICLE_SERVICE_DATA: &str = "service_data";
+const VEHICLE_NEARBY_CHARGING_SITES: &str = "nearby_charging_sites";
 const VEHICLE_DATA: &str = "vehicle_data";
 
 const VEHICLE_COMMAND_WAKE: &str = "wake_up";
@@ -31,11 +51,104 @@ const VEHICLE_COMMAND_FLASH: &str = "flash_lights";
 const VEHICLE_COMMAND_DOOR_UNLOCK: &str = "door_unlock";
 const VEHICLE_COMMAND_DOOR_LOCK: &str = "door_lock";
 const VEHICLE_COMMAND_HONK_HORN: &str = "honk_horn";
+const VEHICLE_COMMAND_REMOTE_BOOMBOX: &str = "remote_boombox";
+const VEHICLE_COMMAND_TRIGGER_HOMELINK: &str = "trigger_homelink";
+const VEHICLE_COMMAND_SET_VEHICLE_NAME: &str = "set_vehicle_name";
+const VEHICLE_COMMAND_GUEST_MODE: &str = "guest_mode";
+const VEHICLE_COMMAND_SCHEDULE_SOFTWARE_UPDATE: &str = "schedule_software_update";
+const VEHICLE_COMMAND_CANCEL_SOFTWARE_UPDATE: &str = "cancel_software_update";
 const VEHICLE_COMMAND_AUTO_CONDITIONING_START: &str = "auto_conditioning_start";
 const VEHICLE_COMMAND_AUTO_CONDITIONING_STOP: &str = "auto_conditioning_stop";
 const VEHICLE_COMMAND_REMOTE_START_DRIVE: &str = "remote_start_drive";
 const VEHICLE_COMMAND_CHARGE_PORT_DOOR_OPEN: &str = "charge_port_door_open";
 const VEHICLE_COMMAND_CHARGE_PORT_DOOR_CLOSE: &str = "charge_port_door_close";
+const VEHICLE_COMMAND_CHARGE_START: &str = "charge_start";
+const VEHICLE_COMMAND_CHARGE_STOP: &str = "charge_stop";
+const VEHICLE_COMMAND_CHARGE_MAX_RANGE: &str = "charge_max_range";
+const VEHICLE_COMMAND_CHARGE_STANDARD: &str = "charge_standard";
+const VEHICLE_COMMAND_SET_CHARGE_LIMIT: &str = "set_charge_limit";
+const VEHICLE_COMMAND_SET_CHARGING_AMPS: &str = "set_charging_amps";
+const VEHICLE_COMMAND_SET_SCHEDULED_CHARGING: &str = "set_scheduled_charging";
+const VEHICLE_COMMAND_SET_SCHEDULED_DEPARTURE: &str = "set_scheduled_departure";
+const VEHICLE_COMMAND_SET_TEMPS: &str = "set_temps";
+const VEHICLE_COMMAND_SEAT_HEATER: &str = "remote_seat_heater_request";
+const VEHICLE_COMMAND_SEAT_COOLER: &str = "remote_seat_cooler_request";
+const VEHICLE_COMMAND_STEERING_WHEEL_HEATER: &str = "remote_steering_wheel_heater_request";
+const VEHICLE_COMMAND_SET_PRECONDITIONING_MAX: &str = "set_preconditioning_max";
+const VEHICLE_COMMAND_SET_CLIMATE_KEEPER_MODE: &str = "set_climate_keeper_mode";
+const VEHICLE_COMMAND_SET_CABIN_OVERHEAT_PROTECTION: &str = "set_cabin_overheat_protection";
+const VEHICLE_COMMAND_SET_BIOWEAPON_MODE: &str = "set_bioweapon_mode";
+const VEHICLE_COMMAND_ACTUATE_TRUNK: &str = "actuate_trunk";
+const VEHICLE_COMMAND_WINDOW_CONTROL: &str = "window_control";
+const VEHICLE_COMMAND_SUN_ROOF_CONTROL: &str = "sun_roof_control";
+const VEHICLE_COMMAND_SET_SENTRY_MODE: &str = "set_sentry_mode";
+const VEHICLE_COMMAND_SET_VALET_MODE: &str = "set_valet_mode";
+const VEHICLE_COMMAND_RESET_VALET_PIN: &str = "reset_valet_pin";
+const VEHICLE_COMMAND_SPEED_LIMIT_ACTIVATE: &str = "speed_limit_activate";
+const VEHICLE_COMMAND_SPEED_LIMIT_DEACTIVATE: &str = "speed_limit_deactivate";
+const VEHICLE_COMMAND_SPEED_LIMIT_SET_LIMIT: &str = "speed_limit_set_limit";
+const VEHICLE_COMMAND_SPEED_LIMIT_CLEAR_PIN: &str = "speed_limit_clear_pin";
+const VEHICLE_COMMAND_SET_PIN_TO_DRIVE: &str = "set_pin_to_drive";
+const VEHICLE_COMMAND_MEDIA_TOGGLE_PLAYBACK: &str = "media_toggle_playback";
+const VEHICLE_COMMAND_MEDIA_NEXT_TRACK: &str = "media_next_track";
+const VEHICLE_COMMAND_MEDIA_PREV_TRACK: &str = "media_prev_track";
+const VEHICLE_COMMAND_MEDIA_NEXT_FAV: &str = "media_next_fav";
+const VEHICLE_COMMAND_MEDIA_PREV_FAV: &str = "media_prev_fav";
+const VEHICLE_COMMAND_MEDIA_VOLUME_UP: &str = "media_volume_up";
+const VEHICLE_COMMAND_MEDIA_VOLUME_DOWN: &str = "media_volume_down";
+const VEHICLE_COMMAND_ADJUST_VOLUME: &str = "adjust_volume";
+const VEHICLE_COMMAND_NAVIGATION_REQUEST: &str = "navigation_request";
+const VEHICLE_COMMAND_NAVIGATION_SC_REQUEST: &str = "navigation_sc_request";
+
+// Charge limits accepted by the vehicle, in percent
+const CHARGE_LIMIT_MIN: u8 = 50;
+const CHARGE_LIMIT_MAX: u8 = 100;
+// Highest current a Tesla can draw, the vehicle caps it to what the charger offers
+const CHARGING_AMPS_MAX: u8 = 48;
+// Cabin temperatures accepted by the vehicle, in celsius
+const TEMPERATURE_MIN: f64 = 15.0;
+const TEMPERATURE_MAX: f64 = 28.0;
+// Speed limits accepted by the vehicle, in mph
+const SPEED_LIMIT_MIN: u8 = 50;
+const SPEED_LIMIT_MAX: u8 = 90;
+// Loudest volume of the media player
+const VOLUME_MAX: f64 = 11.0;
+
+// Commands leaving the vehicle in the same state when sent twice, which can be retried.
+// Sharing a destination or naming the vehicle twice is not harmless, and resending a PIN
+// after a timeout may count as a wrong attempt, so these are never retried.
+const RETRY_SAFE_COMMANDS: &[&str] = &[
+    VEHICLE_COMMAND_WAKE,
+    VEHICLE_COMMAND_DOOR_UNLOCK,
+    VEHICLE_COMMAND_DOOR_LOCK,
+    VEHICLE_COMMAND_AUTO_CONDITIONING_START,
+    VEHICLE_COMMAND_AUTO_CONDITIONING_STOP,
+    VEHICLE_COMMAND_CHARGE_PORT_DOOR_OPEN,
+    VEHICLE_COMMAND_CHARGE_PORT_DOOR_CLOSE,
+    VEHICLE_COMMAND_CHARGE_START,
+    VEHICLE_COMMAND_CHARGE_STOP,
+    VEHICLE_COMMAND_CHARGE_MAX_RANGE,
+    VEHICLE_COMMAND_CHARGE_STANDARD,
+    VEHICLE_COMMAND_SET_CHARGE_LIMIT,
+    VEHICLE_COMMAND_SET_CHARGING_AMPS,
+    VEHICLE_COMMAND_SET_SCHEDULED_CHARGING,
+    VEHICLE_COMMAND_SET_SCHEDULED_DEPARTURE,
+    VEHICLE_COMMAND_SET_TEMPS,
+    VEHICLE_COMMAND_SEAT_HEATER,
+    VEHICLE_COMMAND_SEAT_COOLER,
+    VEHICLE_COMMAND_STEERING_WHEEL_HEATER,
+    VEHICLE_COMMAND_SET_PRECONDITIONING_MAX,
+    VEHICLE_COMMAND_SET_CLIMATE_KEEPER_MODE,
+    VEHICLE_COMMAND_SET_CABIN_OVERHEAT_PROTECTION,
+    VEHICLE_COMMAND_SET_BIOWEAPON_MODE,
+    VEHICLE_COMMAND_WINDOW_CONTROL,
+    VEHICLE_COMMAND_SUN_ROOF_CONTROL,
+    VEHICLE_COMMAND_SET_SENTRY_MODE,
+    VEHICLE_COMMAND_SPEED_LIMIT_SET_LIMIT,
+    VEHICLE_COMMAND_ADJUST_VOLUME,
+    VEHICLE_COMMAND_GUEST_MODE,
+    VEHICLE_COMMAND_CANCEL_SOFTWARE_UPDATE,
+];
 
 // We expect here because this is parsing a const and will not fail
 macro_rules! endpoint_url {
@@ -48,6 +161,9 @@ macro_rules! endpoint_url {
 pub struct TeslaClient {
     pub api_root: reqwest::Url,
     client: Client,
+    token_provider: Arc<dyn TokenProvider>,
+    strict: bool,
+    retry_policy: RetryPolicy,
 }
 
 #[derive(Clone)]
@@ -58,151 +174,62 @@ pub struct VehicleClient {
 
 impl TeslaClient {
     pub async fn authenticate(email: &str, password: &str) -> Result<OAuthToken, TeslaError> {
-        TeslaClient::authenticate_using_api_root(DEFAULT_BASE_URI, email, password).await
+        TeslaClient::authenticate_with_region(&AuthRegion::default(), email, password).await
     }
 
+    /// Authenticates against the SSO routes served by the host of `api_root`, ex: the fake server.
     pub async fn authenticate_using_api_root(api_root: &str, email: &str, password: &str) -> Result<OAuthToken, TeslaError> {
-        let resp = TeslaClient::call_auth_route(api_root, email, password).await?;
+        TeslaClient::authenticate_with_region(&AuthRegion::from_api_root(api_root)?, email, password).await
+    }
+
+    pub async fn authenticate_with_region(region: &AuthRegion, email: &str, password: &str) -> Result<OAuthToken, TeslaError> {
+        TeslaClient::authenticate_with_config(&AuthConfig::new(region), email, password).await
+    }
+
+    /// Logs in with an email and password only, accounts with multi-factor authentication get
+    /// `TeslaError::MfaRequired` (or `TeslaError::CaptchaRequired` when the SSO serves a captcha)
+    /// and have to go through [`TeslaClient::login`].
+    pub async fn authenticate_with_config(config: &AuthConfig, email: &str, password: &str) -> Result<OAuthToken, TeslaError> {
+        let resp = match TeslaClient::login(config, email, password).await? {
+            LoginStep::Token(token) => token,
+            LoginStep::Challenge(LoginChallenge::Mfa(_)) => return Err(TeslaError::MfaRequired),
+            LoginStep::Challenge(LoginChallenge::Captcha(_)) => return Err(TeslaError::CaptchaRequired),
+        };
 
         let expires_in_days = resp.expires_in / 60 / 60 / 24;
         println!("The access token will expire in {} days", expires_in_days);
         Ok(resp)
     }
 
+    /// Starts a login which can be paused by challenges from the SSO, ex: a multi-factor passcode or a captcha.
+    pub async fn login(config: &AuthConfig, email: &str, password: &str) -> Result<LoginStep, TeslaError> {
+        dbg!("start auth steps");
+        login::start_login(config, email, password).await
+    }
+
+    /// Refreshes a token against the region which issued it, falling back to the global SSO.
     pub async fn refresh_token(refresh_token: &str) -> Result<OAuthToken, TeslaError> {
+        let region = AuthRegion::from_token(refresh_token).unwrap_or_default();
+        TeslaClient::refresh_token_with_region(&region, refresh_token).await
+    }
+
+    pub async fn refresh_token_with_region(region: &AuthRegion, refresh_token: &str) -> Result<OAuthToken, TeslaError> {
+        TeslaClient::refresh_token_with_config(&AuthConfig::new(region), refresh_token).await
+    }
+
+    pub async fn refresh_token_with_config(config: &AuthConfig, refresh_token: &str) -> Result<OAuthToken, TeslaError> {
         let mut oauth_refresh_params = HashMap::new();
         oauth_refresh_params.insert("grant_type", "refresh_token");
         oauth_refresh_params.insert("client_id", "ownerapi");
-        oauth_refresh_params.insert("scope", "openid email offline_access");
+        let scope = OWNER_API_SCOPES.join(" ");
+        oauth_refresh_params.insert("scope", scope.as_str());
         oauth_refresh_params.insert("refresh_token", refresh_token);
 
         let client = Client::builder().build().expect("fail to build refresh client");
 
-        let oauth_token_url = Url::parse("https://auth.tesla.cn/oauth2/v3/token").expect("Could not parse oauth token URL");
-        let oauth_response = client.post(oauth_token_url).json(&oauth_refresh_params).send().await?;
-
-        TeslaClient::parse_oauth_token(oauth_response).await
-    }
-
-    async fn call_auth_route(_api_root: &str, email: &str, password: &str) -> Result<OAuthToken, TeslaError> {
-        let auth_endpoint = "https://auth.tesla.cn/oauth2/v3/authorize";
-
-        let policy = Policy::custom(|attempt| {
-            dbg!("redirect to {}", attempt.url());
-            if attempt.url().path() == "/void/callback" {
-                attempt.stop()
-            } else {
-                attempt.follow()
-            }
-        });
-        let client = Client::builder().cookie_store(true).redirect(policy).build().expect("Fail to build auth client");
-
-        dbg!("start auth steps");
-
-        // step 1 get cookie and hidden form field
-        dbg!("auth step1: Obtain the login page");
-        let code_verifier: String = rand::thread_rng().sample_iter(rand::distributions::Alphanumeric).take(86).map(char::from).collect();
-        let mut hasher = Sha256::new();
-        hasher.update(code_verifier.clone());
-        let code_challenge = format!("{:x}", hasher.finalize());
-
-        let state: String = rand::thread_rng().sample_iter(rand::distributions::Alphanumeric).take(16).map(char::from).collect();
-        let mut query_map = HashMap::new();
-        query_map.insert("client_id", "ownerapi");
-        query_map.insert("code_challenge", code_challenge.as_str());
-        query_map.insert("code_challenge_method", "S256");
-        query_map.insert("redirect_uri", "https://auth.tesla.com/void/callback");
-        query_map.insert("response_type", "code");
-        query_map.insert("scope", "openid email offline_access");
-        query_map.insert("state", state.as_str());
-        query_map.insert("login_hint", email);
-
-        let mut url = reqwest::Url::parse(auth_endpoint).expect("Could not parse API URL");
-        url.query_pairs_mut().extend_pairs(query_map.iter());
-        let response = client.get(url).send().await?;
-
-        let body = response.text().await?;
-        dbg!("{}", body.clone());
-
-        // step 2 post to get token
-        dbg!("auth step2: Obtain an authorization code");
-        query_map = HashMap::new();
-        query_map.insert("client_id", "ownerapi");
-        query_map.insert("code_challenge", code_challenge.as_str());
-        query_map.insert("code_challenge_method", "S256");
-        query_map.insert("redirect_uri", "https://auth.tesla.com/void/callback");
-        query_map.insert("response_type", "code");
-        query_map.insert("scope", "openid email offline_access");
-        query_map.insert("state", state.as_str());
-
-        dbg!("====  start to post  ====");
-
-        let mut post_url = reqwest::Url::parse(auth_endpoint).expect("Could not parse API URL");
-        post_url.query_pairs_mut().clear().extend_pairs(query_map.iter());
-
-        let code = TeslaClient::try_post_to_fetch_token(post_url, body.as_str(), email, password, &client).await?;
-        dbg!("code: {}", code.as_str());
-
-        // step 3
-        dbg!("auth step3: Exchange authorization code for bearer token");
-        let mut oauth_token_params = HashMap::new();
-        oauth_token_params.insert("grant_type", "authorization_code");
-        oauth_token_params.insert("client_id", "ownerapi");
-        oauth_token_params.insert("code", code.as_str());
-        oauth_token_params.insert("code_verifier", code_verifier.as_str());
-        oauth_token_params.insert("redirect_uri", "https://auth.tesla.com/void/callback");
-
-        // FIXME: when to use .cn when to use .com ?
-        let oauth_token_url = reqwest::Url::parse("https://auth.tesla.cn/oauth2/v3/token").expect("Could not parse oauth token URL");
-        let oauth_response = client.post(oauth_token_url).json(&oauth_token_params).send().await?;
-
-        let oauth_token = TeslaClient::parse_oauth_token(oauth_response).await;
-        oauth_token
-    }
-
-    #[async_recursion(?Send)]
-    async fn try_post_to_fetch_token(url: Url, body: &str, email: &str, password: &str, client: &reqwest::Client) -> Result<String, TeslaError> {
-        // parse response text
-        let document = Document::from(body);
-        let mut form_values: HashMap<&str, &str> = document.find(And(Name("input"), Attr("type", "hidden")))
-            .map(|e| (e.attr("name").unwrap(), e.attr("value").unwrap())).collect();
-        form_values.insert("identity", email);
-        form_values.insert("credential", password);
-        form_values.insert("privacy_consent", "1");
-
-        let resp = client.post(url).form(&form_values).send().await?;
-
-        if resp.status().is_redirection() {
-            dbg!("post redirection to callback, try to get code from redirect url");
-            match resp.headers().get("location") {
-                None => {
-                    Err(TeslaError::AuthError)
-                }
-                Some(location) => {
-                    let location_str = location.to_str().unwrap();
-                    let redirect_url = reqwest::Url::parse(location_str).expect("Fail to parse auth code location");
-                    let code = redirect_url.query_pairs().find(|q| q.0 == "code").expect("Fail to find code parameter").1;
-                    Ok(code.to_string())
-                }
-            }
-        } else {
-            // still redirect to a login page
-            let final_url = resp.url().clone();
-            dbg!("post redirection to login page {}, try post again", &final_url);
-            let post_resp_body = resp.text().await?;
-            TeslaClient::try_post_to_fetch_token(final_url, post_resp_body.as_str(), email, password, client).await
-        }
-    }
+        let oauth_response = client.post(config.token_url.clone()).json(&oauth_refresh_params).send().await?;
 
-    async fn parse_oauth_token(oauth_response: reqwest::Response) -> Result<OAuthToken, TeslaError> {
-        if oauth_response.status().is_success() {
-            let oauth_token = oauth_response.json::<OAuthToken>().await?;
-            dbg!("oauth response content {}", &oauth_token);
-            Ok(oauth_token)
-        } else {
-            dbg!("oauth response fail,  content {}", oauth_response.text().await?);
-            Err(TeslaError::AuthError)
-        }
+        login::parse_oauth_token(oauth_response).await
     }
 
     pub fn default(access_token: &str) -> TeslaClient {
@@ -210,23 +237,37 @@ impl TeslaClient {
     }
 
     pub fn new(api_root: &str, access_token: &str) -> TeslaClient {
-        let mut headers = header::HeaderMap::new();
-
-        let auth_value = header::HeaderValue::from_str(format!("Bearer {}", access_token).as_str()).unwrap();
-
-        headers.insert(header::AUTHORIZATION, auth_value);
+        TeslaClient::with_token_provider(api_root, StaticToken::new(access_token))
+    }
 
+    /// A client asking `token_provider` for the access token of each request, ex: a [`RefreshingToken`].
+    pub fn with_token_provider<P: TokenProvider + 'static>(api_root: &str, token_provider: P) -> TeslaClient {
         let client = Client::builder()
-            .default_headers(headers)
             .build()
             .expect("Could not create client");
 
         TeslaClient {
             api_root: reqwest::Url::parse(api_root).expect("Could not parse API root"),
             client,
+            token_provider: Arc::new(token_provider),
+            strict: false,
+            retry_policy: RetryPolicy::default(),
         }
     }
 
+    /// Replaces the default policy, retrying up to 3 times.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> TeslaClient {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Reports the commands refused by the vehicle (`result: false`) as [`TeslaError::CommandRejected`]
+    /// instead of a [`SimpleResponse`] to check.
+    pub fn strict(mut self) -> TeslaClient {
+        self.strict = true;
+        self
+    }
+
     pub fn vehicle(&self, vehicle_id: u64) -> VehicleClient {
         VehicleClient {
             tesla_client: self.clone(),
@@ -236,15 +277,18 @@ impl TeslaClient {
 
     pub async fn get_vehicles(&self) -> Result<Vec<Vehicle>, TeslaError> {
         let url = endpoint_url!(self, ENDPOINT_GET_VEHICLES);
-        let response = self.client.get(url).send().await?;
-        if response.status() == 200 {
-            let vehicle_response: ResponseArray<Vehicle> = response.json().await?;
-            Ok(vehicle_response.into_response())
-        } else {
-            Err(self.get_error_from_response(response))
-        }
+        let vehicle_response: ResponseArray<Vehicle> = self.execute(self.client.get(url), true).await?;
+        Ok(vehicle_response.into_response())
+    }
+
+    /// The vehicles and energy sites of the account.
+    pub async fn get_products(&self) -> Result<Vec<Product>, TeslaError> {
+        let url = endpoint_url!(self, ENDPOINT_GET_PRODUCTS);
+        let products: ResponseArray<Product> = self.execute(self.client.get(url), true).await?;
+        Ok(products.into_response())
     }
 
+    /// Finds a vehicle by its name, ignoring the case.
     pub async fn get_vehicle_by_name(&self, name: &str) -> Result<Option<Vehicle>, TeslaError> {
         let vehicle = self.get_vehicles().await?.into_iter()
             .find(|v| v.display_name.to_lowercase() == name.to_lowercase());
@@ -256,28 +300,60 @@ impl TeslaClient {
         self.api_root.clone()
     }
 
-    fn get_error_from_response(&self, response: reqwest::Response) -> TeslaError {
-        let headers = response.headers();
-        let mut err = TeslaError::ParseAppError(AppError {
-            message: "Unspecified error".to_owned()
-        });
-        if response.status() == 401 {
-            let header_value = headers.get("www-authenticate");
-            if header_value.is_some() {
-                if header_value.unwrap().to_str().unwrap_or("").contains("invalid_token") {
-                    err = TeslaError::InvalidTokenError;
-                }
+    /// Sends a request with the current access token, refreshing it once if the API rejects it.
+    async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response, TeslaError> {
+        let retry = request.try_clone();
+        let access_token = self.token_provider.access_token().await?;
+        let response = request.bearer_auth(access_token.as_str()).send().await?;
+
+        match retry {
+            Some(retry) if is_invalid_token(&response) => {
+                let access_token = self.token_provider.refresh(access_token.as_str()).await?;
+                Ok(retry.bearer_auth(access_token).send().await?)
+            }
+            _ => Ok(response)
+        }
+    }
+
+    /// Sends a request and parses its response, retrying it according to the retry policy if `retry_safe`.
+    async fn execute<T: DeserializeOwned>(&self, request: RequestBuilder, retry_safe: bool) -> Result<T, TeslaError> {
+        let mut request = request;
+        let mut attempt = 1;
+        loop {
+            let next = if retry_safe { request.try_clone() } else { None };
+            let result = match self.send(request).await {
+                Ok(response) => self.read_response(response).await,
+                Err(e) => Err(e),
+            };
+
+            match (result, next) {
+                (Err(e), Some(next)) => match self.retry_policy.delay(attempt, &e) {
+                    Some(delay) => {
+                        tokio::time::sleep(delay).await;
+                        request = next;
+                        attempt += 1;
+                    }
+                    None => return Err(e),
+                },
+                (result, _) => return result,
             }
-        } else if response.status() == 404 {
-            err = TeslaError::ParseAppError(AppError {
-                message: "Not found error (404)".to_owned()
-            });
-        } else if response.status() == 408 {
-            err = TeslaError::ParseAppError(AppError {
-                message: "Connect Timeout (408)".to_owned()
-            });
         }
-        err
+    }
+
+    /// Parses the body of a successful response, or turns a failed one into the matching error.
+    async fn read_response<T: DeserializeOwned>(&self, response: reqwest::Response) -> Result<T, TeslaError> {
+        if response.status() != 200 {
+            return Err(TeslaError::from_response(response).await);
+        }
+        let payload = response.text().await?;
+        serde_json::from_str(payload.as_str()).map_err(|error| TeslaError::Deserialize { error, payload })
+    }
+
+    fn check_command(&self, response: SimpleResponse) -> Result<SimpleResponse, TeslaError> {
+        if self.strict && !response.result {
+            return Err(TeslaError::CommandRejected(RejectionReason::from(response.reason.as_str())));
+        }
+        Ok(response)
     }
 }
 
@@ -285,13 +361,9 @@ impl VehicleClient {
     pub async fn wake_up(&self) -> Result<Vehicle, TeslaError> {
         let url = endpoint_url!(self, VEHICLE_COMMAND_WAKE);
 
-        let response = self.tesla_client.client.post(url).send().await?;
-        if response.status() == 200 {
-            let resp: Response<Vehicle> = response.json().await?;
-            Ok(resp.into_response())
-        } else {
-            Err(self.tesla_client.get_error_from_response(response))
-        }
+        let request = self.tesla_client.client.post(url);
+        let resp: Response<Vehicle> = self.tesla_client.execute(request, true).await?;
+        Ok(resp.into_response())
     }
 
     pub async fn flash_lights(&self) -> Result<SimpleResponse, TeslaError> {
@@ -310,6 +382,39 @@ impl VehicleClient {
         self.post_simple_command(VEHICLE_COMMAND_HONK_HORN).await
     }
 
+    /// Plays a sound from the external speaker.
+    pub async fn remote_boombox(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_REMOTE_BOOMBOX).await
+    }
+
+    /// Opens or closes the garage door, the vehicle must be next to the location of the HomeLink device.
+    pub async fn trigger_homelink(&self, lat: f64, lon: f64) -> Result<SimpleResponse, TeslaError> {
+        check_location(lat, lon)?;
+        self.post_command(VEHICLE_COMMAND_TRIGGER_HOMELINK, &json!({ "lat": lat, "lon": lon })).await
+    }
+
+    /// Renames the vehicle, [`TeslaClient::get_vehicle_by_name`] then finds it by its new name.
+    pub async fn set_vehicle_name(&self, name: &str) -> Result<SimpleResponse, TeslaError> {
+        if name.trim().is_empty() {
+            return Err(TeslaError::InvalidArgument("vehicle name must not be empty".to_owned()));
+        }
+        self.post_command(VEHICLE_COMMAND_SET_VEHICLE_NAME, &json!({ "vehicle_name": name })).await
+    }
+
+    /// Installs the downloaded software update in `offset`, right away with a zero offset.
+    pub async fn schedule_software_update(&self, offset: Duration) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SCHEDULE_SOFTWARE_UPDATE, &json!({ "offset_sec": offset.as_secs() })).await
+    }
+
+    pub async fn cancel_software_update(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_CANCEL_SOFTWARE_UPDATE).await
+    }
+
+    /// Restricts the vehicle for a guest driver.
+    pub async fn guest_mode(&self, enable: bool) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_GUEST_MODE, &json!({ "enable": enable })).await
+    }
+
     pub async fn auto_conditioning_start(&self) -> Result<SimpleResponse, TeslaError> {
         self.post_simple_command(VEHICLE_COMMAND_AUTO_CONDITIONING_START).await
     }
@@ -318,16 +423,171 @@ impl VehicleClient {
         self.post_simple_command(VEHICLE_COMMAND_AUTO_CONDITIONING_STOP).await
     }
 
-    pub async fn remote_start_drive(&self) -> Result<SimpleResponse, TeslaError> {
-        // TODO : Need to pass the password in the querystring
-        let url = self.get_command_url(VEHICLE_COMMAND_REMOTE_START_DRIVE);
-        let response = self.tesla_client.client.post(url).send().await?;
-        if response.status() == 200 {
-            let resp: Response<SimpleResponse> = response.json().await?;
-            Ok(resp.into_response())
-        } else {
-            Err(self.tesla_client.get_error_from_response(response))
+    /// Sets the cabin temperatures, in celsius, between 15 and 28.
+    pub async fn set_temps(&self, driver: f64, passenger: f64) -> Result<SimpleResponse, TeslaError> {
+        for temperature in &[driver, passenger] {
+            if !(TEMPERATURE_MIN..=TEMPERATURE_MAX).contains(temperature) {
+                return Err(TeslaError::InvalidArgument(format!(
+                    "temperature must be between {} and {}°C, got {}", TEMPERATURE_MIN, TEMPERATURE_MAX, temperature)));
+            }
         }
+        self.post_command(VEHICLE_COMMAND_SET_TEMPS, &json!({ "driver_temp": driver, "passenger_temp": passenger })).await
+    }
+
+    pub async fn remote_seat_heater_request(&self, seat: Seat, level: SeatLevel) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SEAT_HEATER, &json!({ "heater": seat as u8, "level": level as u8 })).await
+    }
+
+    /// Ventilates a front seat, the rear seats have no cooler.
+    pub async fn remote_seat_cooler_request(&self, seat: Seat, level: SeatLevel) -> Result<SimpleResponse, TeslaError> {
+        let position = seat.cooler_position().ok_or_else(|| {
+            TeslaError::InvalidArgument(format!("{:?} seat has no cooler", seat))
+        })?;
+        self.post_command(VEHICLE_COMMAND_SEAT_COOLER, &json!({ "seat_position": position, "seat_cooler_level": level as u8 })).await
+    }
+
+    pub async fn remote_steering_wheel_heater_request(&self, on: bool) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_STEERING_WHEEL_HEATER, &json!({ "on": on })).await
+    }
+
+    /// Max defrost: heats the cabin at full power to clear ice and snow.
+    pub async fn set_preconditioning_max(&self, on: bool) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SET_PRECONDITIONING_MAX, &json!({ "on": on })).await
+    }
+
+    pub async fn set_climate_keeper_mode(&self, mode: ClimateKeeperMode) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SET_CLIMATE_KEEPER_MODE, &json!({ "climate_keeper_mode": mode as u8 })).await
+    }
+
+    /// Keeps the parked cabin from overheating, with the fans only or the air conditioning.
+    pub async fn set_cabin_overheat_protection(&self, on: bool, fan_only: bool) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SET_CABIN_OVERHEAT_PROTECTION, &json!({ "on": on, "fan_only": fan_only })).await
+    }
+
+    pub async fn set_bioweapon_mode(&self, on: bool) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SET_BIOWEAPON_MODE, &json!({ "on": on, "manual_override": true })).await
+    }
+
+    /// Opens the front trunk, or opens or closes the rear one (on vehicles with a powered trunk).
+    pub async fn actuate_trunk(&self, trunk: Trunk) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_ACTUATE_TRUNK, &json!({ "which_trunk": trunk })).await
+    }
+
+    /// Vents or closes all the windows, closing them needs a location next to the vehicle.
+    pub async fn window_control(&self, command: WindowCommand, lat: f64, lon: f64) -> Result<SimpleResponse, TeslaError> {
+        check_location(lat, lon)?;
+        self.post_command(VEHICLE_COMMAND_WINDOW_CONTROL, &json!({ "command": command, "lat": lat, "lon": lon })).await
+    }
+
+    pub async fn sun_roof_control(&self, state: SunRoofState) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SUN_ROOF_CONTROL, &json!({ "state": state })).await
+    }
+
+    pub async fn set_sentry_mode(&self, on: bool) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SET_SENTRY_MODE, &json!({ "on": on })).await
+    }
+
+    /// Turns the valet mode on or off, with a PIN to turn it off from the vehicle.
+    pub async fn set_valet_mode(&self, on: bool, pin: Option<&Pin>) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SET_VALET_MODE, &json!({ "on": on, "password": pin })).await
+    }
+
+    pub async fn reset_valet_pin(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_RESET_VALET_PIN).await
+    }
+
+    pub async fn speed_limit_activate(&self, pin: &Pin) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SPEED_LIMIT_ACTIVATE, &json!({ "pin": pin })).await
+    }
+
+    pub async fn speed_limit_deactivate(&self, pin: &Pin) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SPEED_LIMIT_DEACTIVATE, &json!({ "pin": pin })).await
+    }
+
+    /// Sets the speed limit, between 50 and 90 mph.
+    pub async fn speed_limit_set_limit(&self, mph: u8) -> Result<SimpleResponse, TeslaError> {
+        if !(SPEED_LIMIT_MIN..=SPEED_LIMIT_MAX).contains(&mph) {
+            return Err(TeslaError::InvalidArgument(format!(
+                "speed limit must be between {} and {} mph, got {}", SPEED_LIMIT_MIN, SPEED_LIMIT_MAX, mph)));
+        }
+        self.post_command(VEHICLE_COMMAND_SPEED_LIMIT_SET_LIMIT, &json!({ "limit_mph": mph })).await
+    }
+
+    pub async fn speed_limit_clear_pin(&self, pin: &Pin) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SPEED_LIMIT_CLEAR_PIN, &json!({ "pin": pin })).await
+    }
+
+    /// Requires a PIN before driving, the PIN is also needed to turn it off.
+    pub async fn set_pin_to_drive(&self, on: bool, pin: &Pin) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SET_PIN_TO_DRIVE, &json!({ "on": on, "password": pin })).await
+    }
+
+    /// Plays or pauses the current media.
+    pub async fn media_toggle_playback(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_MEDIA_TOGGLE_PLAYBACK).await
+    }
+
+    pub async fn media_next_track(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_MEDIA_NEXT_TRACK).await
+    }
+
+    pub async fn media_prev_track(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_MEDIA_PREV_TRACK).await
+    }
+
+    pub async fn media_next_fav(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_MEDIA_NEXT_FAV).await
+    }
+
+    pub async fn media_prev_fav(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_MEDIA_PREV_FAV).await
+    }
+
+    pub async fn media_volume_up(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_MEDIA_VOLUME_UP).await
+    }
+
+    pub async fn media_volume_down(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_MEDIA_VOLUME_DOWN).await
+    }
+
+    /// Sets the media volume, between 0 and 11.
+    pub async fn adjust_volume(&self, level: f64) -> Result<SimpleResponse, TeslaError> {
+        if !(0.0..=VOLUME_MAX).contains(&level) {
+            return Err(TeslaError::InvalidArgument(format!("volume must be between 0 and {}, got {}", VOLUME_MAX, level)));
+        }
+        self.post_command(VEHICLE_COMMAND_ADJUST_VOLUME, &json!({ "volume": level })).await
+    }
+
+    /// Sends a destination to the navigation, `locale` is the language of the address, ex: "en-US".
+    pub async fn share(&self, destination: &Destination, locale: &str) -> Result<SimpleResponse, TeslaError> {
+        if let Destination::Location { lat, lon } = destination {
+            check_location(*lat, *lon)?;
+        }
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        self.post_command(VEHICLE_COMMAND_NAVIGATION_REQUEST, &json!({
+            "type": "share_ext_content_raw",
+            "value": { "android.intent.extra.TEXT": destination.text() },
+            "locale": locale,
+            "timestamp_ms": timestamp_ms.to_string(),
+        })).await
+    }
+
+    /// Navigates to the Supercharger with the given id, as listed by the nearby charging sites.
+    pub async fn navigation_sc_request(&self, id: u64, order: u32) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_NAVIGATION_SC_REQUEST, &json!({ "id": id, "order": order })).await
+    }
+
+    /// Allows to drive without the key for 2 minutes, the API asks for the account password.
+    pub async fn remote_start_drive(&self, password: &Password) -> Result<SimpleResponse, TeslaError> {
+        let url = self.get_command_url(VEHICLE_COMMAND_REMOTE_START_DRIVE);
+        let request = self.tesla_client.client.post(url).query(&[("password", password.expose())]);
+        // the URL holds the password, keep it out of the errors
+        let resp: Response<SimpleResponse> = self.tesla_client.execute(request, false).await.map_err(|e| match e {
+            TeslaError::ParseReqwest(e) => TeslaError::ParseReqwest(e.without_url()),
+            e => e,
+        })?;
+        self.tesla_client.check_command(resp.into_response())
     }
 
     pub async fn charge_port_door_open(&self) -> Result<SimpleResponse, TeslaError> {
@@ -338,15 +598,73 @@ impl VehicleClient {
         self.post_simple_command(VEHICLE_COMMAND_CHARGE_PORT_DOOR_CLOSE).await
     }
 
-    async fn post_simple_command(&self, command: &str) -> Result<SimpleResponse, TeslaError> {
-        let url = self.get_command_url(command);
-        let response = self.tesla_client.client.post(url).send().await?;
-        if response.status() == 200 {
-            let resp: Response<SimpleResponse> = response.json().await?;
-            Ok(resp.into_response())
-        } else {
-            Err(self.tesla_client.get_error_from_response(response))
+    pub async fn charge_start(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_CHARGE_START).await
+    }
+
+    pub async fn charge_stop(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_CHARGE_STOP).await
+    }
+
+    /// Sets the charge limit to 100%.
+    pub async fn charge_max_range(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_CHARGE_MAX_RANGE).await
+    }
+
+    /// Sets the charge limit to the standard 90%.
+    pub async fn charge_standard(&self) -> Result<SimpleResponse, TeslaError> {
+        self.post_simple_command(VEHICLE_COMMAND_CHARGE_STANDARD).await
+    }
+
+    /// Sets the charge limit, between 50 and 100%.
+    pub async fn set_charge_limit(&self, percent: u8) -> Result<SimpleResponse, TeslaError> {
+        if !(CHARGE_LIMIT_MIN..=CHARGE_LIMIT_MAX).contains(&percent) {
+            return Err(TeslaError::InvalidArgument(format!(
+                "charge limit must be between {} and {}%, got {}", CHARGE_LIMIT_MIN, CHARGE_LIMIT_MAX, percent)));
+        }
+        self.post_command(VEHICLE_COMMAND_SET_CHARGE_LIMIT, &json!({ "percent": percent })).await
+    }
+
+    /// Sets the current drawn from the charger, between 1 and 48A.
+    pub async fn set_charging_amps(&self, amps: u8) -> Result<SimpleResponse, TeslaError> {
+        if amps == 0 || amps > CHARGING_AMPS_MAX {
+            return Err(TeslaError::InvalidArgument(format!(
+                "charging current must be between 1 and {}A, got {}", CHARGING_AMPS_MAX, amps)));
         }
+        self.post_command(VEHICLE_COMMAND_SET_CHARGING_AMPS, &json!({ "charging_amps": amps })).await
+    }
+
+    /// Starts charging at `time` once plugged in.
+    pub async fn set_scheduled_charging(&self, enable: bool, time: TimeOfDay) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SET_SCHEDULED_CHARGING, &json!({ "enable": enable, "time": time })).await
+    }
+
+    /// Charges and preconditions the vehicle to be ready at the departure time.
+    pub async fn set_scheduled_departure(&self, enable: bool, departure: &ScheduledDeparture) -> Result<SimpleResponse, TeslaError> {
+        self.post_command(VEHICLE_COMMAND_SET_SCHEDULED_DEPARTURE, &json!({
+            "enable": enable,
+            "departure_time": departure.departure_time,
+            "preconditioning_enabled": departure.preconditioning,
+            "preconditioning_weekdays_only": departure.preconditioning_weekdays_only,
+            "off_peak_charging_enabled": departure.off_peak_charging,
+            "off_peak_charging_weekdays_only": departure.off_peak_charging_weekdays_only,
+            "end_off_peak_time": departure.end_off_peak_time,
+        })).await
+    }
+
+    async fn post_simple_command(&self, command: &str) -> Result<SimpleResponse, TeslaError> {
+        self.post_command_request(command, self.tesla_client.client.post(self.get_command_url(command))).await
+    }
+
+    /// Sends a command with its parameters as a JSON body.
+    async fn post_command<B: Serialize>(&self, command: &str, body: &B) -> Result<SimpleResponse, TeslaError> {
+        let request = self.tesla_client.client.post(self.get_command_url(command)).json(body);
+        self.post_command_request(command, request).await
+    }
+
+    async fn post_command_request(&self, command: &str, request: RequestBuilder) -> Result<SimpleResponse, TeslaError> {
+        let resp: Response<SimpleResponse> = self.tesla_client.execute(request, RETRY_SAFE_COMMANDS.contains(&command)).await?;
+        self.tesla_client.check_command(resp.into_response())
     }
 
     pub async fn get(&self) -> Result<Vehicle, TeslaError> {
@@ -369,14 +687,54 @@ impl VehicleClient {
         self.get_some_data(url).await
     }
 
+    pub async fn get_climate_state(&self) -> Result<ClimateState, TeslaError> {
+        let url = endpoint_url!(self, VEHICLE_CLIMATE_STATE);
+        self.get_some_data(url).await
+    }
+
+    pub async fn get_drive_state(&self) -> Result<DriveState, TeslaError> {
+        let url = endpoint_url!(self, VEHICLE_DRIVE_STATE);
+        self.get_some_data(url).await
+    }
+
+    pub async fn get_vehicle_state(&self) -> Result<VehicleState, TeslaError> {
+        let url = endpoint_url!(self, VEHICLE_STATE);
+        self.get_some_data(url).await
+    }
+
+    pub async fn get_vehicle_config(&self) -> Result<VehicleConfig, TeslaError> {
+        let url = endpoint_url!(self, VEHICLE_CONFIG);
+        self.get_some_data(url).await
+    }
+
+    /// Whether the vehicle allows the mobile access, which the API needs.
+    pub async fn get_mobile_enabled(&self) -> Result<bool, TeslaError> {
+        let url = endpoint_url!(self, VEHICLE_MOBILE_ENABLED);
+        self.get_some_data(url).await
+    }
+
+    pub async fn get_service_data(&self) -> Result<ServiceData, TeslaError> {
+        let url = endpoint_url!(self, VEHICLE_SERVICE_DATA);
+        self.get_some_data(url).await
+    }
+
+    /// The Superchargers and destination chargers around the vehicle, the nearest first.
+    pub async fn nearby_charging_sites(&self) -> Result<NearbyChargingSites, TeslaError> {
+        let url = endpoint_url!(self, VEHICLE_NEARBY_CHARGING_SITES);
+        let mut sites: NearbyChargingSites = self.get_some_data(url).await?;
+        sites.superchargers.sort_by(|a, b| a.distance_miles.total_cmp(&b.distance_miles));
+        sites.destination_charging.sort_by(|a, b| a.distance_miles.total_cmp(&b.distance_miles));
+        Ok(sites)
+    }
+
+    #[deprecated(note = "use `nearby_charging_sites`")]
+    pub async fn get_nearby_charging_sites(&self) -> Result<NearbyChargingSites, TeslaError> {
+        self.nearby_charging_sites().await
+    }
+
     async fn get_some_data<T: DeserializeOwned>(&self, url: reqwest::Url) -> Result<T, TeslaError> {
-        let response = self.tesla_client.client.get(url).send().await?;
-        if response.status() == 200 {
-            let resp: Response<T> = response.json().await?;
-            Ok(resp.into_response())
-        } else {
-            Err(self.tesla_client.get_error_from_response(response))
-        }
+        let resp: Response<T> = self.tesla_client.execute(self.tesla_client.client.get(url), true).await?;
+        Ok(resp.into_response())
     }
 
     fn get_base_url(&self) -> reqwest::Url {
@@ -396,6 +754,19 @@ impl VehicleClient {
     }
 }
 
+fn check_location(lat: f64, lon: f64) -> Result<(), TeslaError> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(TeslaError::InvalidArgument(format!("invalid location: {}, {}", lat, lon)));
+    }
+    Ok(())
+}
+
+fn is_invalid_token(response: &reqwest::Response) -> bool {
+    response.status() == 401 && response.headers().get("www-authenticate")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("invalid_token"))
+        .unwrap_or(false)
+}
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use rand::Rng;
+use reqwest::{Client, Response, Url};
+use reqwest::redirect::Policy;
+use select::document::Document;
+use select::predicate::{And, Attr, Name};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::auth::{AuthConfig, AuthRegion};
+use crate::claims::OWNER_API_SCOPES;
+use crate::models::OAuthToken;
+use crate::tesla_rs_error::{AppError, TeslaError};
+
+// The SSO sometimes serves the login page again instead of redirecting, posting again usually works
+const MAX_LOGIN_ATTEMPTS: usize = 3;
+
+const ENDPOINT_CAPTCHA: &str = "/captcha";
+const ENDPOINT_MFA_FACTORS: &str = "mfa/factors";
+const ENDPOINT_MFA_VERIFY: &str = "mfa/verify";
+
+/// Where a login stands after a step: either done, or waiting for the user to answer a challenge.
+#[derive(Debug)]
+pub enum LoginStep {
+    Token(OAuthToken),
+    Challenge(LoginChallenge),
+}
+
+/// A login paused by the SSO until the user provides more information.
+#[derive(Debug)]
+pub enum LoginChallenge {
+    /// The account has multi-factor authentication enabled, a passcode is needed
+    Mfa(MfaChallenge),
+    /// The SSO wants a captcha solved before accepting the credentials
+    Captcha(CaptchaChallenge),
+}
+
+/// A device registered as second factor on the account.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MfaFactor {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "factorType")]
+    pub factor_type: String,
+}
+
+#[derive(Deserialize)]
+struct MfaResponse<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct MfaVerification {
+    approved: bool,
+    valid: bool,
+}
+
+/// PKCE pair (RFC 7636) binding the authorization code to the client which asked for it.
+#[derive(Debug)]
+struct Pkce {
+    verifier: String,
+    challenge: String,
+}
+
+#[derive(Clone)]
+struct Credentials {
+    email: String,
+    password: String,
+}
+
+/// State kept between the requests of a single login.
+#[derive(Debug)]
+struct LoginSession {
+    client: Client,
+    config: AuthConfig,
+    code_verifier: String,
+    // authorize url with the PKCE query, the form is posted back to it
+    authorize_url: Url,
+}
+
+/// The SSO waits for a passcode from one of the registered factors.
+///
+/// The challenge can be answered again if a passcode is rejected.
+#[derive(Debug)]
+pub struct MfaChallenge {
+    session: Box<LoginSession>,
+    transaction_id: String,
+    factors: Vec<MfaFactor>,
+}
+
+/// The SSO serves a captcha which has to be solved to post the credentials.
+///
+/// A wrong answer leads to a new challenge with a new image.
+#[derive(Debug)]
+pub struct CaptchaChallenge {
+    session: Box<LoginSession>,
+    credentials: Credentials,
+    login_page: String,
+    post_url: Url,
+    image: Vec<u8>,
+    content_type: Option<String>,
+}
+
+/// A login done by the user in a regular web browser.
+///
+/// Open [`BrowserLogin::authorize_url`], log in, then give the URL of the final page
+/// (`.../void/callback?code=...`, a "Page Not Found") to [`BrowserLogin::exchange_callback`].
+#[derive(Debug)]
+pub struct BrowserLogin {
+    config: AuthConfig,
+    pkce: Pkce,
+    state: String,
+    authorize_url: Url,
+}
+
+pub(crate) async fn start_login(config: &AuthConfig, email: &str, password: &str) -> Result<LoginStep, TeslaError> {
+    let session = LoginSession::new(config);
+
+    // step 1 get cookie and hidden form field
+    dbg!("auth step1: Obtain the login page");
+    let mut url = session.authorize_url.clone();
+    url.query_pairs_mut().append_pair("login_hint", email);
+    let response = session.client.get(url).send().await?;
+    let post_url = session.authorize_url.clone();
+    let body = response.text().await?;
+
+    // step 2 post to get token
+    dbg!("auth step2: Obtain an authorization code");
+    let credentials = Credentials {
+        email: email.to_owned(),
+        password: password.to_owned(),
+    };
+    session.post_credentials(body, post_url, credentials, None).await
+}
+
+pub(crate) async fn parse_oauth_token(oauth_response: Response) -> Result<OAuthToken, TeslaError> {
+    if oauth_response.status().is_success() {
+        let oauth_token = oauth_response.json::<OAuthToken>().await?;
+        dbg!("oauth response content {}", &oauth_token);
+        Ok(oauth_token)
+    } else {
+        dbg!("oauth response fail,  content {}", oauth_response.text().await?);
+        Err(TeslaError::AuthError)
+    }
+}
+
+impl LoginSession {
+    fn new(config: &AuthConfig) -> LoginSession {
+        let callback_path = config.redirect_uri.path().to_owned();
+        let policy = Policy::custom(move |attempt| {
+            dbg!("redirect to {}", attempt.url());
+            if attempt.url().path() == callback_path {
+                attempt.stop()
+            } else {
+                attempt.follow()
+            }
+        });
+        let client = Client::builder().cookie_store(true).redirect(policy).build().expect("Fail to build auth client");
+
+        let pkce = Pkce::new();
+        let state = random_string(16);
+
+        LoginSession {
+            client,
+            config: config.clone(),
+            authorize_url: authorize_url(config, &pkce, state.as_str()),
+            code_verifier: pkce.verifier,
+        }
+    }
+
+    async fn post_credentials(self, login_page: String, post_url: Url, credentials: Credentials, mut captcha: Option<String>) -> Result<LoginStep, TeslaError> {
+        let mut page = login_page;
+        let mut post_url = post_url;
+
+        for _ in 0..MAX_LOGIN_ATTEMPTS {
+            if captcha.is_none() && has_captcha(page.as_str()) {
+                dbg!("captcha required");
+                let resp = self.client.get(captcha_url(&post_url, page.as_str())).send().await?;
+                if !resp.status().is_success() {
+                    return Err(TeslaError::AuthError);
+                }
+                let content_type = resp.headers().get("content-type")
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_owned());
+                let image = resp.bytes().await?.to_vec();
+
+                return Ok(LoginStep::Challenge(LoginChallenge::Captcha(CaptchaChallenge {
+                    session: Box::new(self),
+                    credentials,
+                    login_page: page,
+                    post_url,
+                    image,
+                    content_type,
+                })));
+            }
+
+            let mut form_values = hidden_inputs(page.as_str());
+            form_values.insert("identity".to_owned(), credentials.email.clone());
+            form_values.insert("credential".to_owned(), credentials.password.clone());
+            form_values.insert("privacy_consent".to_owned(), "1".to_owned());
+            // an answer is only good for the image it was given for
+            if let Some(answer) = captcha.take() {
+                form_values.insert("captcha".to_owned(), answer);
+            }
+
+            let resp = self.client.post(post_url).form(&form_values).send().await?;
+            if let Some(code) = code_from_redirect(&resp)? {
+                return self.exchange_code(code.as_str()).await.map(LoginStep::Token);
+            }
+            if resp.status().is_client_error() {
+                dbg!("login page rejected the credentials: {}", resp.status());
+                return Err(TeslaError::AuthError);
+            }
+
+            post_url = resp.url().clone();
+            page = resp.text().await?;
+
+            if page.contains(ENDPOINT_MFA_VERIFY) {
+                dbg!("multi-factor authentication required");
+                let transaction_id = hidden_inputs(page.as_str()).remove("transaction_id")
+                    .or_else(|| form_values.remove("transaction_id"))
+                    .ok_or(TeslaError::AuthError)?;
+                let factors = self.mfa_factors(transaction_id.as_str()).await?;
+
+                return Ok(LoginStep::Challenge(LoginChallenge::Mfa(MfaChallenge {
+                    session: Box::new(self),
+                    transaction_id,
+                    factors,
+                })));
+            }
+
+            // still redirect to a login page
+            dbg!("post redirection to login page {}, try post again", &post_url);
+        }
+
+        Err(TeslaError::AuthError)
+    }
+
+    async fn mfa_factors(&self, transaction_id: &str) -> Result<Vec<MfaFactor>, TeslaError> {
+        let mut url = self.mfa_url(ENDPOINT_MFA_FACTORS);
+        url.query_pairs_mut().append_pair("transaction_id", transaction_id);
+
+        let resp = self.client.get(url).send().await?;
+        if !resp.status().is_success() {
+            return Err(TeslaError::AuthError);
+        }
+        let factors: MfaResponse<Vec<MfaFactor>> = resp.json().await?;
+        Ok(factors.data)
+    }
+
+    fn mfa_url(&self, endpoint: &str) -> Url {
+        let mut url = self.config.authorize_url.clone();
+        url.set_query(None);
+        let path = format!("{}/{}", url.path().trim_end_matches('/'), endpoint);
+        url.set_path(path.as_str());
+        url
+    }
+
+    // step 3
+    async fn exchange_code(&self, code: &str) -> Result<OAuthToken, TeslaError> {
+        dbg!("auth step3: Exchange authorization code for bearer token");
+        exchange_code(&self.client, &self.config, self.config.token_url.clone(), code, self.code_verifier.as_str()).await
+    }
+}
+
+impl Pkce {
+    fn new() -> Pkce {
+        Pkce::from_verifier(random_string(86))
+    }
+
+    fn from_verifier(verifier: String) -> Pkce {
+        let mut hasher = Sha256::new();
+        hasher.update(verifier.as_bytes());
+        let challenge = base64::encode_config(hasher.finalize(), base64::URL_SAFE_NO_PAD);
+
+        Pkce {
+            verifier,
+            challenge,
+        }
+    }
+}
+
+impl BrowserLogin {
+    pub fn new(config: &AuthConfig) -> BrowserLogin {
+        let pkce = Pkce::new();
+        let state = random_string(16);
+
+        BrowserLogin {
+            config: config.clone(),
+            authorize_url: authorize_url(config, &pkce, state.as_str()),
+            pkce,
+            state,
+        }
+    }
+
+    /// The SSO login page to open in the browser.
+    pub fn authorize_url(&self) -> &Url {
+        &self.authorize_url
+    }
+
+    /// Exchanges the authorization code carried by the callback URL the browser ended on.
+    pub async fn exchange_callback(&self, callback_url: &str) -> Result<OAuthToken, TeslaError> {
+        let callback = Url::parse(callback_url.trim()).map_err(|_| AppError {
+            message: format!("Could not parse callback URL {}", callback_url)
+        })?;
+        let param = |name: &str| callback.query_pairs().find(|q| q.0 == name).map(|q| q.1.to_string());
+
+        if param("state").as_deref() != Some(self.state.as_str()) {
+            dbg!("callback state does not match the authorize request");
+            return Err(TeslaError::AuthError);
+        }
+        let code = param("code").ok_or_else(|| AppError {
+            message: "No authorization code in callback URL".to_owned()
+        })?;
+
+        // the global SSO sends accounts from other regions to the SSO which issued the code
+        let token_url = param("issuer").as_deref()
+            .and_then(AuthRegion::from_issuer)
+            .map(|region| region.token_url())
+            .unwrap_or_else(|| self.config.token_url.clone());
+
+        let client = Client::builder().build().expect("Fail to build auth client");
+        exchange_code(&client, &self.config, token_url, code.as_str(), self.pkce.verifier.as_str()).await
+    }
+}
+
+impl MfaChallenge {
+    pub fn factors(&self) -> &[MfaFactor] {
+        &self.factors
+    }
+
+    /// Verifies the passcode generated by `factor_id`, then completes the login.
+    ///
+    /// A rejected passcode returns `TeslaError::MfaInvalidPasscode` and leaves the challenge usable.
+    pub async fn submit_passcode(&self, factor_id: &str, passcode: &str) -> Result<LoginStep, TeslaError> {
+        let mut verify_params = HashMap::new();
+        verify_params.insert("transaction_id", self.transaction_id.as_str());
+        verify_params.insert("factor_id", factor_id);
+        verify_params.insert("passcode", passcode);
+
+        let resp = self.session.client.post(self.session.mfa_url(ENDPOINT_MFA_VERIFY)).json(&verify_params).send().await?;
+        if !resp.status().is_success() {
+            return Err(TeslaError::MfaInvalidPasscode);
+        }
+        let verification: MfaResponse<MfaVerification> = resp.json().await?;
+        if !(verification.data.approved && verification.data.valid) {
+            return Err(TeslaError::MfaInvalidPasscode);
+        }
+
+        let mut form_values = HashMap::new();
+        form_values.insert("transaction_id", self.transaction_id.as_str());
+        let resp = self.session.client.post(self.session.authorize_url.clone()).form(&form_values).send().await?;
+
+        match code_from_redirect(&resp)? {
+            Some(code) => self.session.exchange_code(code.as_str()).await.map(LoginStep::Token),
+            None => Err(TeslaError::AuthError)
+        }
+    }
+}
+
+impl CaptchaChallenge {
+    /// The captcha image as served by the SSO, usually an SVG document.
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Posts the credentials again along with the captcha solution, then continues the login.
+    pub async fn submit_answer(self, answer: &str) -> Result<LoginStep, TeslaError> {
+        self.session.post_credentials(self.login_page, self.post_url, self.credentials, Some(answer.to_owned())).await
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("email", &self.email)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng().sample_iter(rand::distributions::Alphanumeric).take(len).map(char::from).collect()
+}
+
+fn authorize_url(config: &AuthConfig, pkce: &Pkce, state: &str) -> Url {
+    let mut url = config.authorize_url.clone();
+    url.query_pairs_mut()
+        .append_pair("client_id", "ownerapi")
+        .append_pair("code_challenge", pkce.challenge.as_str())
+        .append_pair("code_challenge_method", "S256")
+        .append_pair("redirect_uri", config.redirect_uri.as_str())
+        .append_pair("response_type", "code")
+        .append_pair("scope", OWNER_API_SCOPES.join(" ").as_str())
+        .append_pair("state", state);
+    url
+}
+
+async fn exchange_code(client: &Client, config: &AuthConfig, token_url: Url, code: &str, code_verifier: &str) -> Result<OAuthToken, TeslaError> {
+    let mut oauth_token_params = HashMap::new();
+    oauth_token_params.insert("grant_type", "authorization_code");
+    oauth_token_params.insert("client_id", "ownerapi");
+    oauth_token_params.insert("code", code);
+    oauth_token_params.insert("code_verifier", code_verifier);
+    oauth_token_params.insert("redirect_uri", config.redirect_uri.as_str());
+
+    let oauth_response = client.post(token_url).json(&oauth_token_params).send().await?;
+
+    parse_oauth_token(oauth_response).await
+}
+
+fn has_captcha(page: &str) -> bool {
+    Document::from(page).find(And(Name("input"), Attr("name", "captcha"))).next().is_some()
+}
+
+/// The captcha image linked by the login page, or the default SSO route.
+fn captcha_url(page_url: &Url, page: &str) -> Url {
+    Document::from(page).find(Name("img"))
+        .filter_map(|e| e.attr("src"))
+        .find(|src| src.contains("captcha"))
+        .and_then(|src| page_url.join(src).ok())
+        .unwrap_or_else(|| page_url.join(ENDPOINT_CAPTCHA).expect("cannot parse captcha endpoint"))
+}
+
+fn hidden_inputs(page: &str) -> HashMap<String, String> {
+    Document::from(page).find(And(Name("input"), Attr("type", "hidden")))
+        .filter_map(|e| Some((e.attr("name")?.to_owned(), e.attr("value").unwrap_or("").to_owned())))
+        .collect()
+}
+
+/// Reads the authorization code from a redirection to the callback, `None` if the response is not a redirection.
+fn code_from_redirect(resp: &Response) -> Result<Option<String>, TeslaError> {
+    if !resp.status().is_redirection() {
+        return Ok(None);
+    }
+
+    dbg!("post redirection to callback, try to get code from redirect url");
+    // the location may be relative to the SSO host
+    let redirect_url = resp.headers().get("location")
+        .and_then(|location| location.to_str().ok())
+        .and_then(|location| resp.url().join(location).ok())
+        .ok_or(TeslaError::AuthError)?;
+    let code = redirect_url.query_pairs()
+        .find(|q| q.0 == "code")
+        .map(|q| q.1.to_string())
+        .ok_or(TeslaError::AuthError)?;
+
+    Ok(Some(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkce_challenge() {
+        // example from RFC 7636, appendix B
+        let pkce = Pkce::from_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_owned());
+        assert_eq!(pkce.challenge, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+    }
+
+    #[test]
+    fn test_browser_login_authorize_url() {
+        let login = BrowserLogin::new(&AuthConfig::default());
+        let url = login.authorize_url();
+        let param = |name: &str| url.query_pairs().find(|q| q.0 == name).map(|q| q.1.to_string());
+
+        assert_eq!(url.path(), "/oauth2/v3/authorize");
+        assert_eq!(param("code_challenge"), Some(login.pkce.challenge.clone()));
+        assert_eq!(param("state"), Some(login.state.clone()));
+        assert_eq!(param("redirect_uri").as_deref(), Some("https://auth.tesla.com/void/callback"));
+    }
+}
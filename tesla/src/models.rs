@@ -1,12 +1,16 @@
+use std::time::Duration;
+
 use serde::{Serialize, Deserialize};
 
+use crate::commands::{OperationMode, TimeOfDay};
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SimpleResponse {
     pub result: bool,
     pub reason: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Vehicle {
     pub id: u64,
     pub vehicle_id: u64,
@@ -37,6 +41,17 @@ pub struct StateOfCharge {
     pub minutes_to_full_charge: u32,
     pub usable_battery_level: u32,
     pub time_to_full_charge: f64,
+    // "Off", "StartAt" or "DepartBy"
+    pub scheduled_charging_mode: Option<String>,
+    pub scheduled_charging_pending: Option<bool>,
+    pub scheduled_charging_start_time_app: Option<TimeOfDay>,
+    pub scheduled_departure_time_minutes: Option<TimeOfDay>,
+    pub preconditioning_enabled: Option<bool>,
+    // "all_week" or "weekdays"
+    pub preconditioning_times: Option<String>,
+    pub off_peak_charging_enabled: Option<bool>,
+    pub off_peak_charging_times: Option<String>,
+    pub off_peak_hours_end_time: Option<TimeOfDay>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -59,6 +74,60 @@ pub struct VehicleState {
     // front and rear trunk
     pub ft: u8,
     pub rt: u8,
+    pub media_info: Option<MediaState>,
+    pub software_update: Option<SoftwareUpdate>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SoftwareUpdateStatus {
+    #[serde(rename = "")]
+    None,
+    Available,
+    Scheduled,
+    Downloading,
+    // waiting for a wifi connection to download the update
+    DownloadingWifiWait,
+    Installing,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SoftwareUpdate {
+    pub status: SoftwareUpdateStatus,
+    // missing on older firmwares
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub download_perc: u8,
+    #[serde(default)]
+    pub install_perc: u8,
+    pub expected_duration_sec: u64,
+    // when the installation is scheduled, in milliseconds since the UNIX epoch
+    pub scheduled_time_ms: Option<u64>,
+}
+
+impl SoftwareUpdate {
+    pub fn expected_duration(&self) -> Duration {
+        Duration::from_secs(self.expected_duration_sec)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct MediaState {
+    pub media_playback_status: Option<String>,
+    pub now_playing_source: Option<String>,
+    pub now_playing_title: Option<String>,
+    pub now_playing_artist: Option<String>,
+    pub now_playing_album: Option<String>,
+    pub now_playing_station: Option<String>,
+    // in milliseconds
+    pub now_playing_duration: Option<u64>,
+    pub now_playing_elapsed: Option<u64>,
+    pub audio_volume: Option<f64>,
+    pub audio_volume_increment: Option<f64>,
+    pub audio_volume_max: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -123,6 +192,169 @@ pub struct GuiSettings {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+pub struct ServiceData {
+    // "not_in_service" or "in_service"
+    pub service_status: String,
+    // estimated time of completion, while in service
+    pub service_etc: Option<String>,
+    pub service_visit_number: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NearbyChargingSites {
+    pub congestion_sync_time_utc_secs: u64,
+    pub destination_charging: Vec<DestinationCharger>,
+    pub superchargers: Vec<Supercharger>,
+    // in milliseconds since the UNIX epoch
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub lat: f64,
+    #[serde(rename = "long")]
+    pub lon: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Supercharger {
+    pub name: String,
+    pub location: Location,
+    // from the vehicle
+    pub distance_miles: f64,
+    pub available_stalls: u32,
+    pub total_stalls: u32,
+    pub site_closed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DestinationCharger {
+    pub name: String,
+    pub location: Location,
+    // from the vehicle
+    pub distance_miles: f64,
+    pub amenities: Option<String>,
+}
+
+/// A product of the account, as listed by [`crate::TeslaClient::get_products`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Product {
+    Vehicle(Vehicle),
+    EnergySite(EnergySite),
+    /// A product this crate does not know, ex: a wall connector
+    Other(serde_json::Value),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnergySite {
+    pub energy_site_id: u64,
+    // "battery" or "solar"
+    pub resource_type: String,
+    pub site_name: Option<String>,
+    pub id: Option<String>,
+    pub gateway_id: Option<String>,
+    // in Wh
+    pub energy_left: Option<f64>,
+    pub total_pack_energy: Option<f64>,
+    pub percentage_charged: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SiteStatus {
+    pub resource_type: String,
+    pub site_name: Option<String>,
+    pub gateway_id: Option<String>,
+    // in Wh
+    pub energy_left: Option<f64>,
+    pub total_pack_energy: Option<f64>,
+    pub percentage_charged: Option<f64>,
+    pub battery_type: Option<String>,
+    pub backup_capable: Option<bool>,
+    // in W, negative while charging
+    pub battery_power: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LiveStatus {
+    // in W, each one is missing when the site has no such equipment
+    #[serde(default)]
+    pub solar_power: f64,
+    // negative while charging
+    #[serde(default)]
+    pub battery_power: f64,
+    // negative while exporting
+    #[serde(default)]
+    pub grid_power: f64,
+    #[serde(default)]
+    pub load_power: f64,
+    // "Active", or "Inactive" during a grid outage
+    pub grid_status: Option<String>,
+    // in Wh
+    pub energy_left: Option<f64>,
+    pub total_pack_energy: Option<f64>,
+    pub percentage_charged: Option<f64>,
+    pub storm_mode_active: Option<bool>,
+    pub timestamp: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SiteInfo {
+    pub id: String,
+    pub site_name: Option<String>,
+    pub backup_reserve_percent: Option<f64>,
+    // see operation_mode()
+    pub default_real_mode: Option<String>,
+    pub installation_date: Option<String>,
+    pub version: Option<String>,
+    pub battery_count: Option<u32>,
+    // in W and Wh
+    pub nameplate_power: Option<f64>,
+    pub nameplate_energy: Option<f64>,
+    pub user_settings: Option<EnergySiteSettings>,
+}
+
+impl SiteInfo {
+    pub fn operation_mode(&self) -> Option<OperationMode> {
+        self.default_real_mode.as_deref().and_then(|mode| mode.parse().ok())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EnergySiteSettings {
+    pub storm_mode_enabled: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CalendarHistory {
+    pub period: String,
+    pub time_series: Vec<EnergyHistory>,
+}
+
+/// The energy which went from one place to another since `timestamp`, in Wh.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct EnergyHistory {
+    pub timestamp: String,
+    pub solar_energy_exported: f64,
+    pub grid_energy_imported: f64,
+    pub grid_energy_exported_from_solar: f64,
+    pub grid_energy_exported_from_battery: f64,
+    pub battery_energy_exported: f64,
+    pub battery_energy_imported_from_grid: f64,
+    pub battery_energy_imported_from_solar: f64,
+    pub consumer_energy_imported_from_grid: f64,
+    pub consumer_energy_imported_from_solar: f64,
+    pub consumer_energy_imported_from_battery: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EnergySiteCommandResponse {
+    pub code: u16,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OAuthToken {
     pub access_token: String,
     pub refresh_token: String,
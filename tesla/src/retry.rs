@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::tesla_rs_error::TeslaError;
+
+/// How failed requests are retried.
+///
+/// Only the requests which can safely be sent twice are retried: data requests and
+/// idempotent commands (locking the doors, starting the climate...), never a command
+/// like `honk_horn`. Only retryable errors are retried, see [`TeslaError::is_retryable`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Up to `max_attempts` attempts per request, waiting 1s then doubling up to 30s between them.
+    pub fn new(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+
+    /// Every request is sent once.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new(1)
+    }
+
+    pub fn initial_backoff(mut self, backoff: Duration) -> RetryPolicy {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Longest wait between two attempts, a longer Retry-After asked by the API gives up instead.
+    pub fn max_backoff(mut self, backoff: Duration) -> RetryPolicy {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Always waits the full backoff, instead of a random time between half and all of it.
+    pub fn without_jitter(mut self) -> RetryPolicy {
+        self.jitter = false;
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Wait before the retry number `retry` (starting at 1), doubling at each retry.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.max(1).saturating_sub(1).min(31);
+        let backoff = self.initial_backoff.checked_mul(1 << exponent)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+
+        if self.jitter && backoff > Duration::from_millis(1) {
+            let half = backoff / 2;
+            half + rand::thread_rng().gen_range(Duration::from_secs(0)..=half)
+        } else {
+            backoff
+        }
+    }
+
+    /// Wait before retrying after `error`, `None` if it should not be retried.
+    ///
+    /// The delay asked by the API with Retry-After wins over the backoff.
+    pub fn delay(&self, retry: u32, error: &TeslaError) -> Option<Duration> {
+        if retry >= self.max_attempts || !error.is_retryable() {
+            return None;
+        }
+        match error.retry_after() {
+            Some(delay) if delay > self.max_backoff => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff(retry)),
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use crate::tesla_rs_error::ApiError;
+
+    use super::*;
+
+    fn api_error(status: StatusCode) -> ApiError {
+        ApiError { status, message: None, body: String::new() }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(10).max_backoff(Duration::from_secs(5)).without_jitter();
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(4), Duration::from_secs(5));
+        assert_eq!(policy.backoff(100), Duration::from_secs(5));
+
+        let jittered = RetryPolicy::new(10).backoff(2);
+        assert!(jittered >= Duration::from_secs(1) && jittered <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::new(3).without_jitter();
+        let unavailable = TeslaError::VehicleUnavailable(api_error(StatusCode::REQUEST_TIMEOUT));
+        assert_eq!(policy.delay(1, &unavailable), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(3, &unavailable), None);
+        assert_eq!(policy.delay(1, &TeslaError::NotFound(api_error(StatusCode::NOT_FOUND))), None);
+
+        let rate_limited = |seconds| TeslaError::RateLimited {
+            error: api_error(StatusCode::TOO_MANY_REQUESTS),
+            retry_after: Some(Duration::from_secs(seconds)),
+        };
+        assert_eq!(policy.delay(1, &rate_limited(7)), Some(Duration::from_secs(7)));
+        assert_eq!(policy.delay(1, &rate_limited(3600)), None);
+        assert_eq!(RetryPolicy::none().delay(1, &rate_limited(7)), None);
+    }
+}
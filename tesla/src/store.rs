@@ -0,0 +1,252 @@
+use std::convert::TryInto;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use chacha20poly1305::aead::{Aead, NewAead};
+use hmac::Hmac;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::claims::AccessTokenClaims;
+use crate::models::OAuthToken;
+use crate::tesla_rs_error::{AppError, TeslaError};
+
+const KEY_DERIVATION_ROUNDS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// A token as kept between runs, with an absolute expiry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StoredToken {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Expiry of the access token, in seconds since the UNIX epoch, 0 if unknown
+    #[serde(default)]
+    pub expires_at: u64,
+}
+
+/// Somewhere to keep the token between runs.
+pub trait TokenStore: Send + Sync {
+    /// The saved token, `None` if nothing was saved yet.
+    fn load(&self) -> Result<Option<StoredToken>, TeslaError>;
+
+    fn save(&self, token: &StoredToken) -> Result<(), TeslaError>;
+}
+
+/// Keeps the token in a file only readable by its owner, optionally encrypted with a passphrase.
+///
+/// The file is replaced atomically, a crash while saving leaves the previous token in place.
+pub struct FileTokenStore {
+    path: PathBuf,
+    passphrase: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedToken {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum TokenFile {
+    Encrypted(EncryptedToken),
+    Plain(StoredToken),
+}
+
+impl StoredToken {
+    /// Keeps a token just returned by a login or a refresh.
+    pub fn from_oauth_token(token: &OAuthToken) -> StoredToken {
+        let expires_at = SystemTime::now() + Duration::from_secs(token.expires_in.max(0) as u64);
+        StoredToken {
+            access_token: token.access_token.clone(),
+            refresh_token: token.refresh_token.clone(),
+            expires_at: expires_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        }
+    }
+
+    /// The saved expiry, otherwise the one of the access token claims.
+    ///
+    /// A token without any known expiry is considered expired, so that it gets refreshed first.
+    pub fn expiry(&self) -> SystemTime {
+        if self.expires_at > 0 {
+            return UNIX_EPOCH + Duration::from_secs(self.expires_at);
+        }
+        AccessTokenClaims::decode(self.access_token.as_str()).ok()
+            .and_then(|claims| claims.expiry())
+            .unwrap_or(UNIX_EPOCH)
+    }
+}
+
+impl FileTokenStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> FileTokenStore {
+        FileTokenStore {
+            path: path.as_ref().to_path_buf(),
+            passphrase: None,
+        }
+    }
+
+    /// A store encrypting the token with a key derived from `passphrase`.
+    pub fn encrypted<P: AsRef<Path>>(path: P, passphrase: &str) -> FileTokenStore {
+        FileTokenStore {
+            path: path.as_ref().to_path_buf(),
+            passphrase: Some(passphrase.to_owned()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_atomically(&self, content: &[u8]) -> Result<(), TeslaError> {
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = self.path.with_file_name(tmp_name);
+
+        // start from a fresh file so it never has looser permissions than ours
+        let _ = fs::remove_file(&tmp_path);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(&tmp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl TokenStore for FileTokenStore {
+    fn load(&self) -> Result<Option<StoredToken>, TeslaError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read(&self.path)?;
+        let token_file: TokenFile = serde_json::from_slice(&content).map_err(|e| AppError {
+            message: format!("Could not parse token file {}: {}", self.path.display(), e)
+        })?;
+
+        match (token_file, &self.passphrase) {
+            (TokenFile::Plain(token), _) => Ok(Some(token)),
+            (TokenFile::Encrypted(encrypted), Some(passphrase)) => decrypt(&encrypted, passphrase).map(Some),
+            (TokenFile::Encrypted(_), None) => Err(TeslaError::from(AppError {
+                message: format!("Token file {} is encrypted, a passphrase is needed", self.path.display())
+            })),
+        }
+    }
+
+    fn save(&self, token: &StoredToken) -> Result<(), TeslaError> {
+        let token_file = match &self.passphrase {
+            Some(passphrase) => TokenFile::Encrypted(encrypt(token, passphrase)?),
+            None => TokenFile::Plain(token.clone()),
+        };
+        let content = serde_json::to_vec_pretty(&token_file).map_err(|e| AppError {
+            message: format!("Could not serialize token: {}", e)
+        })?;
+
+        self.write_atomically(&content)
+    }
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> XChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, KEY_DERIVATION_ROUNDS, &mut key);
+    XChaCha20Poly1305::new(&Key::from(key))
+}
+
+fn encrypt(token: &StoredToken, passphrase: &str) -> Result<EncryptedToken, TeslaError> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let plaintext = serde_json::to_vec(token).map_err(|e| AppError {
+        message: format!("Could not serialize token: {}", e)
+    })?;
+    let ciphertext = cipher(passphrase, &salt).encrypt(&XNonce::from(nonce), plaintext.as_slice())
+        .map_err(|_| AppError {
+            message: "Could not encrypt token".to_owned()
+        })?;
+
+    Ok(EncryptedToken {
+        salt: base64::encode(salt),
+        nonce: base64::encode(nonce),
+        ciphertext: base64::encode(ciphertext),
+    })
+}
+
+fn decrypt(encrypted: &EncryptedToken, passphrase: &str) -> Result<StoredToken, TeslaError> {
+    let invalid = || AppError {
+        message: "Could not decrypt token, wrong passphrase?".to_owned()
+    };
+
+    let salt = base64::decode(&encrypted.salt).map_err(|_| invalid())?;
+    let nonce: [u8; NONCE_LEN] = base64::decode(&encrypted.nonce).ok()
+        .and_then(|n| n.try_into().ok())
+        .ok_or_else(invalid)?;
+    let ciphertext = base64::decode(&encrypted.ciphertext).map_err(|_| invalid())?;
+
+    let plaintext = cipher(passphrase, &salt).decrypt(&XNonce::from(nonce), ciphertext.as_slice())
+        .map_err(|_| invalid())?;
+    serde_json::from_slice(&plaintext).map_err(|_| invalid().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tesla-store-{}", rand::thread_rng().next_u64()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    fn token() -> StoredToken {
+        StoredToken {
+            access_token: "access".to_owned(),
+            refresh_token: "refresh".to_owned(),
+            expires_at: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn test_file_store_roundtrip() {
+        let store = FileTokenStore::new(temp_path("token.json"));
+        assert_eq!(store.load().unwrap(), None);
+
+        store.save(&token()).unwrap();
+        assert_eq!(store.load().unwrap(), Some(token()));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(store.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_encrypted_file_store() {
+        let path = temp_path("token.json");
+        let store = FileTokenStore::encrypted(&path, "correct horse");
+        store.save(&token()).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("refresh"));
+        assert_eq!(store.load().unwrap(), Some(token()));
+
+        assert!(FileTokenStore::encrypted(&path, "battery staple").load().is_err());
+        assert!(FileTokenStore::new(&path).load().is_err());
+    }
+}
@@ -1,7 +1,11 @@
 use std::fmt;
 use std::error;
+use std::io;
+use std::time::{Duration, SystemTime};
 
 use reqwest;
+use reqwest::StatusCode;
+use serde::Deserialize;
 
 #[derive(Debug)]
 pub struct AppError {
@@ -20,23 +24,221 @@ impl fmt::Display for AppError {
     }
 }
 
+/// An error status returned by the API, with what it said about it.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    /// The `error` field of a JSON body, ex: `vehicle unavailable: ...`
+    pub message: Option<String>,
+    /// The raw body
+    pub body: String,
+}
+
+/// Why the vehicle refused a command, from the `reason` of its response.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RejectionReason {
+    /// The vehicle is already in the requested state
+    AlreadySet,
+    VehicleUnavailable,
+    CouldNotWakeBuses,
+    NotCharging,
+    IsCharging,
+    ChargingComplete,
+    Disconnected,
+    UserNotPresent,
+    InService,
+    Other(String),
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: Option<String>,
+}
 
 #[derive(Debug)]
 pub enum TeslaError {
     ParseAppError(AppError),
     AuthError,
     InvalidTokenError,
+    MfaRequired,
+    MfaInvalidPasscode,
+    CaptchaRequired,
     ParseReqwest(reqwest::Error),
+    IoError(io::Error),
+    /// The vehicle is asleep or offline (408)
+    VehicleUnavailable(ApiError),
+    /// Too many requests (429), `retry_after` is the delay asked by the API
+    RateLimited { error: ApiError, retry_after: Option<Duration> },
+    /// Any 5xx
+    ServerError(ApiError),
+    NotFound(ApiError),
+    Forbidden(ApiError),
+    /// Any other unexpected status
+    HttpError(ApiError),
+    /// A successful response which could not be parsed, `payload` is the raw body
+    Deserialize { error: serde_json::Error, payload: String },
+    /// The vehicle refused a command, only returned by a [`strict`](crate::TeslaClient::strict) client
+    CommandRejected(RejectionReason),
+    /// A command parameter out of the range accepted by the vehicle, the command was not sent
+    InvalidArgument(String),
     SystemError
 }
 
+impl RejectionReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            RejectionReason::AlreadySet => "already_set",
+            RejectionReason::VehicleUnavailable => "vehicle_unavailable",
+            RejectionReason::CouldNotWakeBuses => "could_not_wake_buses",
+            RejectionReason::NotCharging => "not_charging",
+            RejectionReason::IsCharging => "is_charging",
+            RejectionReason::ChargingComplete => "complete",
+            RejectionReason::Disconnected => "disconnected",
+            RejectionReason::UserNotPresent => "user_not_present",
+            RejectionReason::InService => "in_service",
+            RejectionReason::Other(reason) => reason.as_str(),
+        }
+    }
+}
+
+impl From<&str> for RejectionReason {
+    fn from(reason: &str) -> Self {
+        match reason {
+            "already_set" => RejectionReason::AlreadySet,
+            "vehicle_unavailable" => RejectionReason::VehicleUnavailable,
+            "could_not_wake_buses" => RejectionReason::CouldNotWakeBuses,
+            "not_charging" => RejectionReason::NotCharging,
+            "is_charging" => RejectionReason::IsCharging,
+            "complete" => RejectionReason::ChargingComplete,
+            "disconnected" => RejectionReason::Disconnected,
+            "user_not_present" => RejectionReason::UserNotPresent,
+            "in_service" => RejectionReason::InService,
+            other => RejectionReason::Other(other.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl ApiError {
+    pub(crate) async fn from_response(response: reqwest::Response) -> ApiError {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<ErrorBody>(body.as_str()).ok().and_then(|b| b.error);
+        ApiError { status, message, body }
+    }
+}
+
+impl TeslaError {
+    /// Builds the error matching the status of a failed response.
+    pub(crate) async fn from_response(response: reqwest::Response) -> TeslaError {
+        if crate::is_invalid_token(&response) {
+            return TeslaError::InvalidTokenError;
+        }
+        let retry_after = retry_after(&response);
+        let error = ApiError::from_response(response).await;
+        match error.status {
+            StatusCode::UNAUTHORIZED => TeslaError::AuthError,
+            StatusCode::FORBIDDEN => TeslaError::Forbidden(error),
+            StatusCode::NOT_FOUND => TeslaError::NotFound(error),
+            StatusCode::REQUEST_TIMEOUT => TeslaError::VehicleUnavailable(error),
+            StatusCode::TOO_MANY_REQUESTS => TeslaError::RateLimited { error, retry_after },
+            s if s.is_server_error() => TeslaError::ServerError(error),
+            _ => TeslaError::HttpError(error),
+        }
+    }
+
+    /// The status returned by the API, if the error comes from one.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            TeslaError::VehicleUnavailable(e)
+            | TeslaError::RateLimited { error: e, .. }
+            | TeslaError::ServerError(e)
+            | TeslaError::NotFound(e)
+            | TeslaError::Forbidden(e)
+            | TeslaError::HttpError(e) => Some(e.status),
+            TeslaError::ParseReqwest(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    /// Whether the same request may succeed later: the vehicle may wake up, the server recover...
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TeslaError::VehicleUnavailable(_)
+            | TeslaError::RateLimited { .. }
+            | TeslaError::ServerError(_) => true,
+            TeslaError::CommandRejected(reason) => matches!(reason,
+                RejectionReason::VehicleUnavailable | RejectionReason::CouldNotWakeBuses),
+            TeslaError::ParseReqwest(e) => e.is_timeout() || e.is_connect(),
+            _ => false,
+        }
+    }
+
+    /// Whether logging in again, or with more permissions, is needed.
+    pub fn is_auth_error(&self) -> bool {
+        matches!(self,
+            TeslaError::AuthError
+            | TeslaError::InvalidTokenError
+            | TeslaError::MfaRequired
+            | TeslaError::MfaInvalidPasscode
+            | TeslaError::CaptchaRequired
+            | TeslaError::Forbidden(_))
+    }
+
+    /// How long the API asked to wait before retrying.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            TeslaError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}: {}", self.status, message),
+            None if !self.body.is_empty() => write!(f, "{}: {}", self.status, self.body),
+            None => write!(f, "{}", self.status),
+        }
+    }
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    match value.trim().parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => httpdate::parse_http_date(value).ok()
+            .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default()),
+    }
+}
+
 impl error::Error for TeslaError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
             TeslaError::ParseAppError(ref e) => Some(e),
             TeslaError::AuthError => None,
             TeslaError::InvalidTokenError => None,
+            TeslaError::MfaRequired => None,
+            TeslaError::MfaInvalidPasscode => None,
+            TeslaError::CaptchaRequired => None,
             TeslaError::ParseReqwest(ref e) => Some(e),
+            TeslaError::IoError(ref e) => Some(e),
+            TeslaError::VehicleUnavailable(_) => None,
+            TeslaError::RateLimited { .. } => None,
+            TeslaError::ServerError(_) => None,
+            TeslaError::NotFound(_) => None,
+            TeslaError::Forbidden(_) => None,
+            TeslaError::HttpError(_) => None,
+            TeslaError::Deserialize { ref error, .. } => Some(error),
+            TeslaError::CommandRejected(_) => None,
+            TeslaError::InvalidArgument(_) => None,
             TeslaError::SystemError => None
         }
     }
@@ -48,7 +250,22 @@ impl fmt::Display for TeslaError {
             TeslaError::ParseAppError(ref e) => e.fmt(f),
             TeslaError::AuthError => write!(f, "Authentication error!"),
             TeslaError::InvalidTokenError => write!(f, "Invalid token error!"),
+            TeslaError::MfaRequired => write!(f, "Multi-factor authentication required!"),
+            TeslaError::MfaInvalidPasscode => write!(f, "Invalid multi-factor passcode!"),
+            TeslaError::CaptchaRequired => write!(f, "Captcha required!"),
             TeslaError::ParseReqwest(ref e) => e.fmt(f),
+            TeslaError::IoError(ref e) => e.fmt(f),
+            TeslaError::VehicleUnavailable(ref e) => write!(f, "Vehicle unavailable ({})", e),
+            TeslaError::RateLimited { ref error, retry_after: Some(delay) } =>
+                write!(f, "Rate limited, retry in {}s ({})", delay.as_secs(), error),
+            TeslaError::RateLimited { ref error, retry_after: None } => write!(f, "Rate limited ({})", error),
+            TeslaError::ServerError(ref e) => write!(f, "Server error ({})", e),
+            TeslaError::NotFound(ref e) => write!(f, "Not found ({})", e),
+            TeslaError::Forbidden(ref e) => write!(f, "Forbidden ({})", e),
+            TeslaError::HttpError(ref e) => write!(f, "Unexpected response ({})", e),
+            TeslaError::Deserialize { ref error, .. } => write!(f, "Could not parse response: {}", error),
+            TeslaError::CommandRejected(ref reason) => write!(f, "Command rejected: {}", reason),
+            TeslaError::InvalidArgument(ref message) => write!(f, "Invalid argument: {}", message),
             TeslaError::SystemError => write!(f, "System error!"),
         }
     }
@@ -65,3 +282,9 @@ impl From<reqwest::Error> for TeslaError {
         TeslaError::ParseReqwest(err)
     }
 }
+
+impl From<io::Error> for TeslaError {
+    fn from(err: io::Error) -> TeslaError {
+        TeslaError::IoError(err)
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::auth::AuthConfig;
+use crate::models::OAuthToken;
+use crate::store::{StoredToken, TokenStore};
+use crate::tesla_rs_error::{AppError, TeslaError};
+use crate::TeslaClient;
+
+// Refresh a bit before the expiry so a request does not leave with a token about to expire
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Source of the access token sent with every API request.
+#[async_trait]
+pub trait TokenProvider: Send + Sync {
+    /// The access token to use for the next request.
+    async fn access_token(&self) -> Result<String, TeslaError>;
+
+    /// Called once when the API rejects `rejected_token` as invalid, returns the token to retry with.
+    async fn refresh(&self, rejected_token: &str) -> Result<String, TeslaError>;
+}
+
+/// A fixed access token, requests fail once it expires.
+pub struct StaticToken {
+    access_token: String,
+}
+
+type RefreshCallback = Box<dyn Fn(&OAuthToken) + Send + Sync>;
+
+/// An access token refreshed before it expires, or when the API rejects it.
+///
+/// Tesla rotates the refresh token on every refresh, build it with [`RefreshingToken::from_store`]
+/// or use [`RefreshingToken::on_refresh`] to persist the new one. When the store fails to save it,
+/// the request which triggered the refresh fails, and the callback is not invoked.
+pub struct RefreshingToken {
+    auth_config: AuthConfig,
+    state: Mutex<TokenState>,
+    store: Option<Box<dyn TokenStore>>,
+    on_refresh: Option<RefreshCallback>,
+}
+
+struct TokenState {
+    access_token: String,
+    refresh_token: String,
+    expires_at: SystemTime,
+}
+
+impl StaticToken {
+    pub fn new(access_token: &str) -> StaticToken {
+        StaticToken {
+            access_token: access_token.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl TokenProvider for StaticToken {
+    async fn access_token(&self) -> Result<String, TeslaError> {
+        Ok(self.access_token.clone())
+    }
+
+    async fn refresh(&self, _rejected_token: &str) -> Result<String, TeslaError> {
+        Err(TeslaError::InvalidTokenError)
+    }
+}
+
+impl RefreshingToken {
+    pub fn new(auth_config: AuthConfig, access_token: &str, refresh_token: &str, expires_at: SystemTime) -> RefreshingToken {
+        RefreshingToken {
+            auth_config,
+            state: Mutex::new(TokenState {
+                access_token: access_token.to_owned(),
+                refresh_token: refresh_token.to_owned(),
+                expires_at,
+            }),
+            store: None,
+            on_refresh: None,
+        }
+    }
+
+    /// Starts from the token saved in `store`, and saves every new token to it.
+    pub fn from_store<S: TokenStore + 'static>(auth_config: AuthConfig, store: S) -> Result<RefreshingToken, TeslaError> {
+        let token = store.load()?.ok_or_else(|| AppError {
+            message: "No token saved, please log in".to_owned()
+        })?;
+
+        let mut provider = RefreshingToken::new(auth_config, token.access_token.as_str(), token.refresh_token.as_str(), token.expiry());
+        provider.store = Some(Box::new(store));
+        Ok(provider)
+    }
+
+    /// Starts from a token just returned by a login or a refresh.
+    pub fn from_oauth_token(auth_config: AuthConfig, token: &OAuthToken) -> RefreshingToken {
+        let expires_at = SystemTime::now() + Duration::from_secs(token.expires_in.max(0) as u64);
+        RefreshingToken::new(auth_config, token.access_token.as_str(), token.refresh_token.as_str(), expires_at)
+    }
+
+    /// Sets a callback invoked with every new token, ex: to save it.
+    pub fn on_refresh<F>(mut self, callback: F) -> RefreshingToken
+        where F: Fn(&OAuthToken) + Send + Sync + 'static {
+        self.on_refresh = Some(Box::new(callback));
+        self
+    }
+
+    async fn refresh_locked(&self, state: &mut TokenState) -> Result<String, TeslaError> {
+        let token = TeslaClient::refresh_token_with_config(&self.auth_config, state.refresh_token.as_str()).await?;
+
+        state.access_token = token.access_token.clone();
+        state.refresh_token = token.refresh_token.clone();
+        state.expires_at = SystemTime::now() + Duration::from_secs(token.expires_in.max(0) as u64);
+
+        // the previous refresh token is no longer valid, losing the new one means logging in again
+        if let Some(store) = &self.store {
+            store.save(&StoredToken::from_oauth_token(&token)).map_err(|e| AppError {
+                message: format!("Token refreshed but could not be saved, the next run will need to log in again: {}", e)
+            })?;
+        }
+        if let Some(callback) = &self.on_refresh {
+            callback(&token);
+        }
+
+        Ok(token.access_token)
+    }
+}
+
+#[async_trait]
+impl TokenProvider for RefreshingToken {
+    async fn access_token(&self) -> Result<String, TeslaError> {
+        let mut state = self.state.lock().await;
+        if SystemTime::now() + REFRESH_MARGIN >= state.expires_at {
+            return self.refresh_locked(&mut state).await;
+        }
+        Ok(state.access_token.clone())
+    }
+
+    async fn refresh(&self, rejected_token: &str) -> Result<String, TeslaError> {
+        let mut state = self.state.lock().await;
+        // another request may have refreshed it in the meantime
+        if state.access_token != rejected_token {
+            return Ok(state.access_token.clone());
+        }
+        self.refresh_locked(&mut state).await
+    }
+}
@@ -3,7 +3,9 @@ use serde::Serialize;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
-    pub token: Token,
+    /// Only read to move the token saved by older versions to the token store
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<Token>,
     pub global: GlobalConfig,
     pub influx: Option<InfluxConfig>,
     #[cfg(feature = "sqlite")]
@@ -16,7 +18,11 @@ pub struct Config {
 pub struct GlobalConfig {
     pub default_vehicle: Option<String>,
     pub default_vehicle_id: Option<u64>,
+    /// Energy site polled by the daemon and the influx reporter, next to the vehicle
+    pub default_energy_site_id: Option<u64>,
     pub logspec: Option<String>,
+    pub auth_region: Option<String>,
+    pub token_file: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -61,15 +67,14 @@ mod tests {
     #[test]
     fn test_global_config_serialize() {
         let config = Config {
-            token: Token {
-                access_token: "access_token".to_string(),
-                refresh_token: "refresh_token".to_string(),
-                expires_ts: 0,
-            },
+            token: None,
             global: GlobalConfig {
                 default_vehicle: None,
                 default_vehicle_id: None,
+                default_energy_site_id: None,
                 logspec: Some("info".to_string()),
+                auth_region: None,
+                token_file: None,
             },
             influx: None,
             #[cfg(feature = "sqlite")]
@@ -88,4 +93,21 @@ mod tests {
             }
         };
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_legacy_token_deserialize() {
+        let config: Config = toml::from_str(r#"
+            [token]
+            access_token = "access_token"
+            refresh_token = "refresh_token"
+            expires_ts = 0
+
+            [global]
+            logspec = "info"
+        "#).unwrap();
+        assert_eq!(config.token.unwrap().refresh_token, "refresh_token");
+
+        let config: Config = toml::from_str("[global]\n").unwrap();
+        assert!(config.token.is_none());
+    }
+}
@@ -6,18 +6,19 @@ use std::time::{Duration, Instant};
 use influx_db_client::{InfluxClient, Point, Precision, Value};
 use snafu::ResultExt;
 
-use tesla::{TeslaClient, Vehicle, VehicleClient, StateOfCharge, VehicleState, ClimateState, DriveState};
+use tesla::{TeslaClient, Vehicle, VehicleClient, StateOfCharge, VehicleState, ClimateState, DriveState, EnergySiteClient};
 
 use crate::config::InfluxConfig;
 use crate::error::{Error, TeslaApi, InfluxWrite};
 
-pub async fn run_influx_reporter(cfg: InfluxConfig, vehicle_name: String, client: TeslaClient) -> Result<(), Error> {
+pub async fn run_influx_reporter(cfg: InfluxConfig, vehicle_name: String, energy_site_id: Option<u64>, client: TeslaClient) -> Result<(), Error> {
     let vehicle = client.get_vehicle_by_name(vehicle_name.as_str()).await
         .ok()
         .expect("could not find vehicle")
         .expect("could not find vehicle");
 
     let vclient = client.vehicle(vehicle.id);
+    let energy_site = energy_site_id.map(|id| (id, client.energy_site(id)));
     let influxc = get_influx_client(cfg.clone());
 
     let running = Arc::new(AtomicBool::new(true));
@@ -34,6 +35,9 @@ pub async fn run_influx_reporter(cfg: InfluxConfig, vehicle_name: String, client
         if Instant::now() > next_poll_time {
             debug!("Reporting to influx");
             check_and_report(&vclient, &influxc).await?;
+            if let Some((energy_site_id, site)) = &energy_site {
+                report_energy(site, *energy_site_id, &influxc).await?;
+            }
 
             next_poll_time = Instant::now() + Duration::from_secs(poll_duration);
         }
@@ -75,6 +79,24 @@ async fn report_online(client: &VehicleClient, vehicle: &Vehicle, influx: &Influ
     Ok(())
 }
 
+async fn report_energy(site: &EnergySiteClient, energy_site_id: u64, client: &InfluxClient) -> Result<(), Error> {
+    info!("Reporting energy site power to influx");
+    let live_status = site.live_status().await.context(TeslaApi)?;
+    let mut power: Point = point!("energy");
+
+    power.add_field("solar_power", Value::Float(live_status.solar_power));
+    power.add_field("battery_power", Value::Float(live_status.battery_power));
+    power.add_field("grid_power", Value::Float(live_status.grid_power));
+    power.add_field("load_power", Value::Float(live_status.load_power));
+    if let Some(percentage_charged) = live_status.percentage_charged {
+        power.add_field("percentage_charged", Value::Float(percentage_charged));
+    }
+    power.add_tag("energy_site_id", Value::Integer(energy_site_id as i64));
+
+    client.write_point(power, Some(Precision::Milliseconds), None)
+        .context(InfluxWrite)
+}
+
 fn report_loc(vehicle: &Vehicle, drive_state: &DriveState, client: &InfluxClient) -> Result<(), Error> {
     let mut loc: Point = point!("location");
 
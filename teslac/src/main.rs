@@ -6,24 +6,28 @@ extern crate log;
 extern crate rpassword;
 
 use std::borrow::Borrow;
+use std::env;
 use std::fs;
+use std::future::Future;
 use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::thread::sleep;
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant};
 use std::io::{stdin, stdout, Write};
-use std::ops::Add;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 use clap::{App, Arg, ArgMatches, SubCommand};
 use dirs::home_dir;
 
-use tesla::{TeslaClient, TeslaError, OAuthToken};
+use tesla::{AccessTokenClaims, AuthConfig, AuthRegion, BrowserLogin, CaptchaChallenge, Destination, FileTokenStore, LoginChallenge, LoginStep, MfaChallenge,
+            RefreshingToken, RetryPolicy, SimpleResponse, SoftwareUpdateStatus, StoredToken, SunRoofState, TeslaClient, TeslaError, TokenStore, OAuthToken,
+            Password, Trunk, VehicleClient, WindowCommand, DEFAULT_BASE_URI, OWNER_API_SCOPES};
 
-use crate::config::{Config, GlobalConfig, Token};
+use crate::config::{Config, GlobalConfig};
 #[cfg(feature = "influxdb")]
 use crate::influx::run_influx_reporter;
+use crate::sink::Record;
 
 mod config;
 #[cfg(feature = "influxdb")]
@@ -32,6 +36,9 @@ mod influx;
 mod error;
 mod sink;
 
+const MAX_MFA_ATTEMPTS: usize = 3;
+const TOKEN_PASSPHRASE_ENV: &str = "TESLAC_TOKEN_PASSPHRASE";
+
 #[tokio::main]
 async fn main() {
     std::process::exit(match run().await {
@@ -68,6 +75,13 @@ async fn run() -> Result<(), ()> {
                 .help("Performs authentication with the Tesla servers using the prompted email address and password. Returns an oauth token when successful.")
                 .takes_value(false)
         )
+        .arg(
+            Arg::with_name("auth-region")
+                .long("auth-region")
+                .value_name("REGION")
+                .help("Tesla SSO to authenticate against: global (default), china, or the base URL of a compatible server.")
+                .takes_value(true)
+        )
         .arg(
             Arg::with_name("vehicle")
                 .long("vehicle")
@@ -76,6 +90,25 @@ async fn run() -> Result<(), ()> {
                 .global(true)
                 .takes_value(true)
         )
+        .subcommand(
+            SubCommand::with_name("auth")
+                .about("Manage the authentication with the Tesla servers")
+                .subcommand(
+                    SubCommand::with_name("login")
+                        .about("Log in and save the token to the token file")
+                        .arg(
+                            Arg::with_name("browser")
+                                .help("Log in with a web browser, then paste the URL of the page it ends on")
+                                .long("browser")
+                                .short("b")
+                                .takes_value(false)
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("status")
+                        .about("Show the region, scopes and remaining lifetime of the saved token")
+                )
+        )
         .subcommand(
             SubCommand::with_name("wake")
                 .about("wake up the specified vehicle")
@@ -111,6 +144,122 @@ async fn run() -> Result<(), ()> {
             SubCommand::with_name("door_lock")
                 .about("lock the doors for the specified vehicle")
         )
+        .subcommand(
+            SubCommand::with_name("actuate_trunk")
+                .about("open the front trunk, or open or close the rear trunk of the specified vehicle")
+                .arg(
+                    Arg::with_name("trunk")
+                        .help("Trunk to actuate")
+                        .possible_values(&["front", "rear"])
+                        .required(true)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("window_control")
+                .about("vent or close the windows of the specified vehicle")
+                .arg(
+                    Arg::with_name("command")
+                        .help("What to do with the windows")
+                        .possible_values(&["vent", "close"])
+                        .required(true)
+                )
+                .arg(
+                    Arg::with_name("lat")
+                        .help("Latitude next to the vehicle, defaults to the vehicle location")
+                        .long("lat")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .requires("lon")
+                )
+                .arg(
+                    Arg::with_name("lon")
+                        .help("Longitude next to the vehicle, defaults to the vehicle location")
+                        .long("lon")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .requires("lat")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("sun_roof_control")
+                .about("vent or close the sunroof of the specified vehicle")
+                .arg(
+                    Arg::with_name("state")
+                        .help("State to move the sunroof to")
+                        .possible_values(&["vent", "close", "stop"])
+                        .required(true)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("remote_start")
+                .about("allow to drive the specified vehicle without its key for 2 minutes, asks for the account password")
+        )
+        .subcommand(
+            SubCommand::with_name("navigate")
+                .about("send a destination to the navigation of the specified vehicle")
+                .arg(
+                    Arg::with_name("address")
+                        .help("Address or place to navigate to")
+                        .required_unless("lat")
+                        .conflicts_with("lat")
+                )
+                .arg(
+                    Arg::with_name("lat")
+                        .help("Latitude of the destination")
+                        .long("lat")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .requires("lon")
+                )
+                .arg(
+                    Arg::with_name("lon")
+                        .help("Longitude of the destination")
+                        .long("lon")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .requires("lat")
+                )
+                .arg(
+                    Arg::with_name("locale")
+                        .help("Language of the address")
+                        .long("locale")
+                        .takes_value(true)
+                        .default_value("en-US")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("chargers")
+                .about("List the Superchargers and destination chargers near the specified vehicle, the nearest first")
+                .arg(
+                    Arg::with_name("json")
+                        .help("Print the chargers as JSON")
+                        .long("json")
+                        .takes_value(false)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("update")
+                .about("Manage the software updates of the specified vehicle")
+                .subcommand(
+                    SubCommand::with_name("status")
+                        .about("Show the pending software update, if any")
+                )
+                .subcommand(
+                    SubCommand::with_name("install")
+                        .about("Install the downloaded software update")
+                        .arg(
+                            Arg::with_name("delay")
+                                .help("Minutes to wait before installing")
+                                .long("delay")
+                                .takes_value(true)
+                                .default_value("0")
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("cancel")
+                        .about("Cancel the scheduled software update")
+                )
+        )
         .subcommand(
             SubCommand::with_name("influx")
                 .about("Start the influxdb reporter")
@@ -130,11 +279,20 @@ async fn run() -> Result<(), ()> {
 
     let debug_server = matches.value_of("debug-server");
     if debug_server.is_some() {
-        println!("Using the debug server : {}", debug_server.unwrap());
+        eprintln!("Using the debug server : {}", debug_server.unwrap());
     }
 
+    let region_arg = match matches.value_of("auth-region").map(|r| r.parse::<AuthRegion>()) {
+        Some(Ok(r)) => Some(r),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return Err(());
+        }
+        None => None
+    };
+
     if matches.is_present("oauth") {
-        let token = auth_interactive(debug_server).await;
+        let token = auth_interactive(debug_server, &region_arg.clone().unwrap_or_default()).await;
         return if token.is_ok() {
             println!("Your token is: {}", token.unwrap().access_token);
             Ok(())
@@ -153,62 +311,81 @@ async fn run() -> Result<(), ()> {
         .unwrap_or(config_path_default);
     let cfg = get_config(config_path.borrow(), debug_server.is_some());
 
+    if let Some(submatches) = matches.subcommand_matches("auth") {
+        return if let Some(login_matches) = submatches.subcommand_matches("login") {
+            let region = region_arg.unwrap_or_default();
+            let token = if login_matches.is_present("browser") {
+                auth_browser(debug_server, &region).await
+            } else {
+                auth_interactive(debug_server, &region).await
+            };
+            match token.and_then(|t| save_token(cfg, &config_path, t, &region)) {
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    println!("failed to get token: {}", e);
+                    Err(())
+                }
+            }
+        } else if submatches.subcommand_matches("status").is_some() {
+            match cfg {
+                Some(mut c) => {
+                    let store = token_store(&config_path, &c);
+                    if let Err(e) = migrate_legacy_token(&mut c, &config_path, &store) {
+                        eprintln!("{}", e);
+                        return Err(());
+                    }
+                    token_status(&store)
+                }
+                None => {
+                    println!("Not logged in, run `teslac auth login`");
+                    Err(())
+                }
+            }
+        } else {
+            println!("No auth command specified");
+            Err(())
+        }
+    }
+
     let mut config = match cfg {
         None => {
             // without config, go to auth progress
-            let token = auth_interactive(debug_server).await;
-            match token {
-                Ok(t) => {
-                    let expires_in = t.expires_in;
-                    // get current timestamp
-                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-                    let expire_time = now.add(Duration::from_secs(expires_in as u64));
-                    let new_config = Config {
-                        token: Token {
-                            access_token: t.access_token,
-                            refresh_token: t.refresh_token,
-                            expires_ts: expire_time.as_secs(),
-                        },
-                        global: GlobalConfig {
-                            default_vehicle: None,
-                            default_vehicle_id: None,
-                            logspec: Some("info".to_string())
-                        },
-                        influx: None,
-                        #[cfg(feature = "sqlite")]
-                        sqlite: None,
-                        #[cfg(feature = "mqtt")]
-                        mqtt: None,
-                    };
-
-                    match toml::ser::to_string(&new_config) {
-                        Ok(str_content) => {
-                            let _ = fs::write(&config_path, str_content);
-                            println!("Config file created at {}", config_path.display());
-                        }
+            login_and_save(None, &config_path, debug_server, region_arg.unwrap_or_default()).await?
+        }
+        Some(mut c) => {
+            if let Some(region) = region_arg {
+                c.global.auth_region = Some(region.to_string());
+            }
+            let store = token_store(&config_path, &c);
+            match migrate_legacy_token(&mut c, &config_path, &store).and_then(|_| store.load()) {
+                Ok(Some(_)) => c,
+                Ok(None) => {
+                    // nothing saved yet, go to auth progress
+                    let region = match auth_region(&c, None) {
+                        Ok(r) => r,
                         Err(e) => {
-                            eprintln!("Error writing config: {}", e);
+                            eprintln!("{}", e);
+                            return Err(());
                         }
-                    }
-
-                    new_config
+                    };
+                    login_and_save(Some(c), &config_path, debug_server, region).await?
                 }
                 Err(e) => {
-                    println!("failed to get token: {}", e);
-                    return Err(())
+                    eprintln!("{}", e);
+                    return Err(());
                 }
             }
         }
-        Some(c) => {
-            try_refresh_token(c, &config_path).await.expect("failed to refresh token")
-        }
     };
 
-    let client = if debug_server.is_some() {
-        TeslaClient::new(debug_server.unwrap(), config.token.access_token.as_str())
-    } else {
-        TeslaClient::default(config.token.access_token.as_str())
+    let token_provider = match token_provider(&config, &config_path, debug_server) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(());
+        }
     };
+    let client = TeslaClient::with_token_provider(debug_server.unwrap_or(DEFAULT_BASE_URI), token_provider).strict();
 
     flexi_logger::Logger::with_env_or_str(config.global.logspec.clone().unwrap_or("".to_owned()))
         .format(flexi_logger::colored_with_thread)
@@ -232,23 +409,74 @@ async fn run() -> Result<(), ()> {
     } else if let Some(_submatches) = matches.subcommand_matches("get_all_data") {
         get_all_data(vehicle_name, client.clone()).await;
     } else if let Some(_submatches) = matches.subcommand_matches("flash_lights") {
-        flash_lights(vehicle_name, client.clone()).await;
+        flash_lights(vehicle_name, client.clone()).await?;
     } else if let Some(_submatches) = matches.subcommand_matches("door_unlock") {
-        door_unlock(vehicle_name, client.clone()).await;
+        door_unlock(vehicle_name, client.clone()).await?;
     } else if let Some(_submatches) = matches.subcommand_matches("door_lock") {
-        door_lock(vehicle_name, client.clone()).await;
+        door_lock(vehicle_name, client.clone()).await?;
+    } else if let Some(submatches) = matches.subcommand_matches("actuate_trunk") {
+        let trunk = submatches.value_of("trunk").unwrap().parse().expect("Could not parse trunk");
+        actuate_trunk(vehicle_name, client.clone(), trunk).await?;
+    } else if let Some(submatches) = matches.subcommand_matches("window_control") {
+        let command = submatches.value_of("command").unwrap().parse().expect("Could not parse window command");
+        let location = match (submatches.value_of("lat"), submatches.value_of("lon")) {
+            (Some(lat), Some(lon)) => Some((
+                lat.parse().expect("Could not parse latitude"),
+                lon.parse().expect("Could not parse longitude"),
+            )),
+            _ => None
+        };
+        window_control(vehicle_name, client.clone(), command, location).await?;
+    } else if let Some(submatches) = matches.subcommand_matches("sun_roof_control") {
+        let state = submatches.value_of("state").unwrap().parse().expect("Could not parse sunroof state");
+        sun_roof_control(vehicle_name, client.clone(), state).await?;
+    } else if let Some(_submatches) = matches.subcommand_matches("remote_start") {
+        remote_start(vehicle_name, client.clone()).await?;
+    } else if let Some(submatches) = matches.subcommand_matches("navigate") {
+        let destination = match (submatches.value_of("lat"), submatches.value_of("lon")) {
+            (Some(lat), Some(lon)) => Destination::Location {
+                lat: lat.parse().expect("Could not parse latitude"),
+                lon: lon.parse().expect("Could not parse longitude"),
+            },
+            _ => Destination::Address(submatches.value_of("address").unwrap().to_owned()),
+        };
+        let locale = submatches.value_of("locale").unwrap().to_owned();
+        navigate(vehicle_name, client.clone(), destination, locale).await?;
+    } else if let Some(submatches) = matches.subcommand_matches("chargers") {
+        chargers(vehicle_name, client.clone(), submatches.is_present("json")).await?;
+    } else if let Some(submatches) = matches.subcommand_matches("update") {
+        if submatches.subcommand_matches("status").is_some() {
+            update_status(vehicle_name, client.clone()).await?;
+        } else if let Some(install_matches) = submatches.subcommand_matches("install") {
+            let delay = install_matches.value_of("delay").unwrap();
+            let offset = match delay.parse::<u64>().ok().and_then(|d| d.checked_mul(60)) {
+                Some(seconds) => Duration::from_secs(seconds),
+                None => {
+                    error!("Invalid delay {}, expected a number of minutes", delay);
+                    return Err(());
+                }
+            };
+            send_command(vehicle_name, client.clone(), "scheduling software update",
+                         |v| async move { v.schedule_software_update(offset).await }).await?;
+        } else if submatches.subcommand_matches("cancel").is_some() {
+            send_command(vehicle_name, client.clone(), "cancelling software update",
+                         |v| async move { v.cancel_software_update().await }).await?;
+        } else {
+            error!("Missing update subcommand, see teslac update --help");
+            return Err(());
+        }
     } else if let Some(_submatches) = matches.subcommand_matches("influx") {
         if config.influx.is_none() {
             error!("No influx configuration present, cannot start influx reporter!");
             return Err(());
         }
         #[cfg(feature = "influxdb")]
-        if let Err(e) = run_influx_reporter(config.influx.unwrap(), vehicle_name, client.clone()).await {
+        if let Err(e) = run_influx_reporter(config.influx.unwrap(), vehicle_name, config.global.default_energy_site_id, client.clone()).await {
             error!("Error in influx reporter: {}", e);
             exit(1);
         }
     } else if let Some(_submatches) = matches.subcommand_matches("daemon") {
-        start_read_daemon(config, &config_path, vehicle_name, client.clone()).await;
+        start_read_daemon(config, vehicle_name, client.clone()).await;
     } else {
         println!("No command specified")
     }
@@ -278,6 +506,154 @@ fn get_config(config_path: &Path, has_debug_server: bool) -> Option<Config> {
     Some(cfg)
 }
 
+async fn login_and_save(cfg: Option<Config>, config_path: &Path, debug_server: Option<&str>, region: AuthRegion) -> Result<Config, ()> {
+    match auth_interactive(debug_server, &region).await.and_then(|t| save_token(cfg, config_path, t, &region)) {
+        Ok(config) => Ok(config),
+        Err(e) => {
+            println!("failed to get token: {}", e);
+            Err(())
+        }
+    }
+}
+
+/// Stores a new token in the token store, creating the config file if needed.
+fn save_token(cfg: Option<Config>, config_path: &Path, token: OAuthToken, region: &AuthRegion) -> Result<Config, TeslaError> {
+    let created = cfg.is_none();
+    let mut config = cfg.unwrap_or_else(|| Config {
+        token: None,
+        global: GlobalConfig {
+            default_vehicle: None,
+            default_vehicle_id: None,
+            default_energy_site_id: None,
+            logspec: Some("info".to_string()),
+            auth_region: None,
+            token_file: None,
+        },
+        influx: None,
+        #[cfg(feature = "sqlite")]
+        sqlite: None,
+        #[cfg(feature = "mqtt")]
+        mqtt: None,
+    });
+    // the new token replaces any token left in the config by older versions
+    config.token = None;
+    config.global.auth_region = Some(region.to_string());
+
+    match toml::ser::to_string(&config) {
+        Ok(str_content) => {
+            let _ = fs::write(config_path, str_content);
+            if created {
+                println!("Config file created at {}", config_path.display());
+            }
+        }
+        Err(e) => {
+            eprintln!("Error writing config: {}", e);
+        }
+    }
+
+    let store = token_store(config_path, &config);
+    store.save(&StoredToken::from_oauth_token(&token))?;
+    println!("Token saved to {}", store.path().display());
+
+    Ok(config)
+}
+
+/// The token file: `global.token_file`, otherwise next to the config file.
+///
+/// It is encrypted when a passphrase is given in the environment.
+fn token_store(config_path: &Path, config: &Config) -> FileTokenStore {
+    let path = config.global.token_file.as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            let mut path = config_path.as_os_str().to_os_string();
+            path.push(".token");
+            PathBuf::from(path)
+        });
+
+    match env::var(TOKEN_PASSPHRASE_ENV) {
+        Ok(passphrase) if !passphrase.is_empty() => FileTokenStore::encrypted(path, passphrase.as_str()),
+        _ => FileTokenStore::new(path),
+    }
+}
+
+/// Moves a token saved in the config file by older versions to the token store.
+fn migrate_legacy_token(config: &mut Config, config_path: &Path, store: &FileTokenStore) -> Result<(), TeslaError> {
+    if let Some(token) = config.token.take() {
+        if store.load()?.is_none() {
+            store.save(&StoredToken {
+                access_token: token.access_token,
+                refresh_token: token.refresh_token,
+                expires_at: token.expires_ts,
+            })?;
+            println!("Token moved from {} to {}", config_path.display(), store.path().display());
+        }
+        update_config(config_path, |c| c.token = None);
+    }
+    Ok(())
+}
+
+/// Rewrites the config file with `update` applied to its current content.
+fn update_config<F: FnOnce(&mut Config)>(config_path: &Path, update: F) {
+    if let Some(mut config) = get_config(config_path, false) {
+        update(&mut config);
+        match toml::to_string(&config) {
+            Ok(str_content) => {
+                let _ = fs::write(config_path, str_content);
+            }
+            Err(e) => {
+                error!("Error writing config: {}", e);
+            }
+        }
+    }
+}
+
+/// Prints what the saved access token is valid for.
+fn token_status(store: &FileTokenStore) -> Result<(), ()> {
+    let claims = match store.load() {
+        Ok(Some(token)) => AccessTokenClaims::decode(token.access_token.as_str()),
+        Ok(None) => {
+            println!("Not logged in, run `teslac auth login`");
+            return Err(());
+        }
+        Err(e) => Err(e),
+    };
+    let claims = match claims {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(());
+        }
+    };
+
+    println!("Token file: {}", store.path().display());
+    println!("Issuer: {}", claims.issuer);
+    println!("Region: {}", claims.region().map(|r| r.to_string()).unwrap_or_else(|| "unknown".to_owned()));
+    println!("Audience: {}", claims.audience.join(", "));
+    println!("Scopes: {}", claims.scopes.join(" "));
+    match claims.remaining_lifetime() {
+        Some(left) if left.as_secs() == 0 => println!("Expires: expired, it will be refreshed on next use"),
+        Some(left) => println!("Expires in: {}h{:02}m", left.as_secs() / 3600, left.as_secs() % 3600 / 60),
+        None => println!("Expires: unknown"),
+    }
+
+    let missing = claims.missing_scopes(&OWNER_API_SCOPES);
+    if !missing.is_empty() {
+        println!("Warning: the token lacks the {} scopes, log in again with `teslac auth login`", missing.join(", "));
+    }
+    Ok(())
+}
+
+/// Refreshes the stored token when needed, saving the new one to the token store.
+fn token_provider(config: &Config, config_path: &Path, debug_server: Option<&str>) -> Result<RefreshingToken, TeslaError> {
+    let store = token_store(config_path, config);
+    let auth_config = auth_config(debug_server, &auth_region(config, store.load()?.as_ref())?)?;
+    let token_path = store.path().to_path_buf();
+
+    let provider = RefreshingToken::from_store(auth_config, store)?
+        .on_refresh(move |_| info!("Token refreshed, saved it to {}", token_path.display()));
+    Ok(provider)
+}
+
 async fn cmd_wake(matches: &ArgMatches<'_>, name: String, client: TeslaClient) {
     if let Some(vehicle) = client.get_vehicle_by_name(name.as_str()).await.expect("Could not load vehicles") {
         let vclient = client.vehicle(vehicle.id);
@@ -329,63 +705,233 @@ async fn get_all_data(name: String, client: TeslaClient) {
     }
 }
 
-async fn flash_lights(name: String, client: TeslaClient) {
-    if let Some(vehicle) = client.get_vehicle_by_name(name.as_str()).await.expect("Could not load vehicles") {
-        let vclient = client.vehicle(vehicle.id);
-        info!("flashing lights");
-        match vclient.flash_lights().await {
-            Ok(_) => info!("Success"),
-            Err(e) => error!("flashing lights failed {:?}", e)
+async fn flash_lights(name: String, client: TeslaClient) -> Result<(), ()> {
+    send_command(name, client, "flashing lights", |v| async move { v.flash_lights().await }).await
+}
+
+async fn door_unlock(name: String, client: TeslaClient) -> Result<(), ()> {
+    send_command(name, client, "unlocking doors", |v| async move { v.door_unlock().await }).await
+}
+
+async fn door_lock(name: String, client: TeslaClient) -> Result<(), ()> {
+    send_command(name, client, "locking doors", |v| async move { v.door_lock().await }).await
+}
+
+async fn actuate_trunk(name: String, client: TeslaClient, trunk: Trunk) -> Result<(), ()> {
+    send_command(name, client, "actuating trunk", |v| async move { v.actuate_trunk(trunk).await }).await
+}
+
+async fn window_control(name: String, client: TeslaClient, command: WindowCommand, location: Option<(f64, f64)>) -> Result<(), ()> {
+    send_command(name, client, "controlling windows", |v| async move {
+        let (lat, lon) = match location {
+            Some(location) => location,
+            None => {
+                let drive_state = v.get_drive_state().await?;
+                (drive_state.latitude, drive_state.longitude)
+            }
+        };
+        v.window_control(command, lat, lon).await
+    }).await
+}
+
+async fn sun_roof_control(name: String, client: TeslaClient, state: SunRoofState) -> Result<(), ()> {
+    send_command(name, client, "moving sunroof", |v| async move { v.sun_roof_control(state).await }).await
+}
+
+async fn remote_start(name: String, client: TeslaClient) -> Result<(), ()> {
+    let password = Password::new(rpassword::prompt_password_stdout("Password: ").unwrap());
+    send_command(name, client, "starting remotely", |v| async move { v.remote_start_drive(&password).await }).await
+}
+
+async fn navigate(name: String, client: TeslaClient, destination: Destination, locale: String) -> Result<(), ()> {
+    send_command(name, client, "sending destination", |v| async move { v.share(&destination, locale.as_str()).await }).await
+}
+
+async fn chargers(name: String, client: TeslaClient, json: bool) -> Result<(), ()> {
+    let vehicle = find_vehicle(&client, name.as_str()).await?;
+    let sites = match vehicle.nearby_charging_sites().await {
+        Ok(sites) => sites,
+        Err(e) => {
+            error!("Could not get the nearby chargers: {}", e);
+            return Err(());
         }
-    } else {
-        error!("Could not find vehicle named {}", name);
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&sites).unwrap());
+        return Ok(());
+    }
+
+    let mut lines: Vec<(f64, String)> = sites.superchargers.iter()
+        .map(|s| {
+            let stalls = if s.site_closed {
+                "closed".to_owned()
+            } else {
+                format!("{}/{} stalls available", s.available_stalls, s.total_stalls)
+            };
+            (s.distance_miles, format!("{:>6.1} mi  Supercharger  {}  ({})", s.distance_miles, s.name, stalls))
+        })
+        .chain(sites.destination_charging.iter()
+            .map(|d| (d.distance_miles, format!("{:>6.1} mi  Destination   {}", d.distance_miles, d.name))))
+        .collect();
+    lines.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    if lines.is_empty() {
+        println!("No charger nearby");
+    }
+    for (_, line) in lines {
+        println!("{}", line);
     }
+    Ok(())
 }
 
-async fn door_unlock(name: String, client: TeslaClient) {
-    if let Some(vehicle) = client.get_vehicle_by_name(name.as_str()).await.expect("Could not load vehicles") {
-        let vclient = client.vehicle(vehicle.id);
-        info!("unlocking doors");
-        match vclient.door_unlock().await {
-            Ok(_) => info!("Success"),
-            Err(e) => error!("unlocking doors failed {:?}", e)
+async fn update_status(name: String, client: TeslaClient) -> Result<(), ()> {
+    let vehicle = find_vehicle(&client, name.as_str()).await?;
+    let vehicle_state = match vehicle.get_all_data().await {
+        Ok(data) => data.vehicle_state,
+        Err(e) => {
+            error!("get data failed: {}", e);
+            return Err(());
+        }
+    };
+
+    println!("Current version: {}", vehicle_state.car_version);
+    match vehicle_state.software_update {
+        Some(update) if update.status != SoftwareUpdateStatus::None => {
+            println!("Update {}: {:?}", update.version.trim(), update.status);
+            println!("Downloaded: {}%, installed: {}%", update.download_perc, update.install_perc);
+            println!("Expected duration: {} minutes", update.expected_duration().as_secs() / 60);
+        }
+        _ => println!("No pending update"),
+    }
+    Ok(())
+}
+
+async fn find_vehicle(client: &TeslaClient, name: &str) -> Result<VehicleClient, ()> {
+    match client.get_vehicle_by_name(name).await {
+        Ok(Some(vehicle)) => Ok(client.vehicle(vehicle.id)),
+        Ok(None) => {
+            error!("Could not find vehicle named {}", name);
+            Err(())
+        }
+        Err(e) => {
+            error!("Could not load vehicles: {}", e);
+            Err(())
         }
-    } else {
-        error!("Could not find vehicle named {}", name);
     }
 }
 
-async fn door_lock(name: String, client: TeslaClient) {
+/// Sends a command to the named vehicle, a command refused by the vehicle is reported as a failure.
+async fn send_command<F, Fut>(name: String, client: TeslaClient, description: &str, command: F) -> Result<(), ()>
+    where F: FnOnce(VehicleClient) -> Fut, Fut: Future<Output = Result<SimpleResponse, TeslaError>> {
     if let Some(vehicle) = client.get_vehicle_by_name(name.as_str()).await.expect("Could not load vehicles") {
-        let vclient = client.vehicle(vehicle.id);
-        info!("locking doors");
-        match vclient.door_lock().await {
-            Ok(_) => info!("Success"),
-            Err(e) => error!("locking doors failed {:?}", e)
+        info!("{}", description);
+        match command(client.vehicle(vehicle.id)).await {
+            Ok(_) => {
+                info!("Success");
+                Ok(())
+            }
+            Err(e) => {
+                error!("{} failed: {}", description, e);
+                Err(())
+            }
         }
     } else {
         error!("Could not find vehicle named {}", name);
+        Err(())
     }
 }
 
-async fn auth_interactive(debug_server: Option<&str>) -> Result<OAuthToken, TeslaError> {
-    let mut email = String::new();
-    print!("Please enter your email: ");
-    let _ = stdout().flush();
-    stdin().read_line(&mut email).expect("Did not enter a correct string");
-    email = email.replace("\n", "").replace("\r", "");
+async fn auth_interactive(debug_server: Option<&str>, region: &AuthRegion) -> Result<OAuthToken, TeslaError> {
+    let email = read_line("Please enter your email: ");
 
     let password = rpassword::prompt_password_stdout("Password: ").unwrap();
-    let token = if debug_server.is_some() {
-        TeslaClient::authenticate_using_api_root(debug_server.unwrap(), email.as_str(), password.as_str()).await
-    } else {
-        TeslaClient::authenticate(email.as_str(), password.as_str()).await
+    let auth_config = auth_config(debug_server, region)?;
+
+    let mut step = TeslaClient::login(&auth_config, email.as_str(), password.as_str()).await?;
+    loop {
+        step = match step {
+            LoginStep::Token(token) => return Ok(token),
+            LoginStep::Challenge(LoginChallenge::Mfa(mfa)) => prompt_mfa_passcode(&mfa).await?,
+            LoginStep::Challenge(LoginChallenge::Captcha(captcha)) => prompt_captcha(captcha).await?,
+        };
+    }
+}
+
+async fn auth_browser(debug_server: Option<&str>, region: &AuthRegion) -> Result<OAuthToken, TeslaError> {
+    let login = BrowserLogin::new(&auth_config(debug_server, region)?);
+
+    println!("Open this URL in your web browser and log in:\n\n{}\n", login.authorize_url());
+    println!("The browser then ends on a \"Page Not Found\", copy the full URL of that page.");
+    let callback_url = read_line("URL: ");
+
+    login.exchange_callback(callback_url.as_str()).await
+}
+
+fn auth_config(debug_server: Option<&str>, region: &AuthRegion) -> Result<AuthConfig, TeslaError> {
+    match debug_server {
+        Some(api_root) => Ok(AuthConfig::new(&AuthRegion::from_api_root(api_root)?)),
+        None => Ok(AuthConfig::new(region))
+    }
+}
+
+async fn prompt_mfa_passcode(mfa: &MfaChallenge) -> Result<LoginStep, TeslaError> {
+    let factor = match mfa.factors() {
+        [] => return Err(TeslaError::AuthError),
+        [factor] => factor,
+        factors => {
+            println!("Multi-factor authentication is enabled, please select a device:");
+            for (i, f) in factors.iter().enumerate() {
+                println!("[{}], {}", i + 1, f.name);
+            }
+            let index: usize = read_line("Please enter index: ").parse().expect("Did not enter a correct index");
+            if index > factors.len() || index < 1 {
+                return Err(TeslaError::SystemError);
+            }
+            &factors[index - 1]
+        }
     };
 
-    token
+    for _ in 0..MAX_MFA_ATTEMPTS {
+        let passcode = read_line(format!("Passcode from {}: ", factor.name).as_str());
+        match mfa.submit_passcode(factor.id.as_str(), passcode.as_str()).await {
+            Err(TeslaError::MfaInvalidPasscode) => println!("Invalid passcode, please try again"),
+            result => return result,
+        }
+    }
+
+    Err(TeslaError::MfaInvalidPasscode)
+}
+
+async fn prompt_captcha(captcha: CaptchaChallenge) -> Result<LoginStep, TeslaError> {
+    let extension = match captcha.content_type() {
+        Some(t) if t.contains("svg") => "svg",
+        Some(t) if t.contains("png") => "png",
+        Some(t) if t.contains("jpeg") => "jpg",
+        _ => "img"
+    };
+    let image_path = std::env::temp_dir().join(format!("teslac-captcha.{}", extension));
+    if let Err(e) = fs::write(&image_path, captcha.image()) {
+        eprintln!("Error writing captcha image: {}", e);
+        return Err(TeslaError::SystemError);
+    }
+
+    println!("Tesla asks to solve a captcha, the image has been written to {}", image_path.display());
+    let answer = read_line("Captcha: ");
+    let _ = fs::remove_file(&image_path);
+
+    captcha.submit_answer(answer.as_str()).await
+}
+
+fn read_line(prompt: &str) -> String {
+    let mut input = String::new();
+    print!("{}", prompt);
+    let _ = stdout().flush();
+    stdin().read_line(&mut input).expect("Did not enter a correct string");
+    input.replace("\n", "").replace("\r", "")
 }
 
-async fn choose_vehicle(config: &mut Config, config_path: &PathBuf, client: TeslaClient) -> Result<String, TeslaError> {
+async fn choose_vehicle(config: &mut Config, config_path: &Path, client: TeslaClient) -> Result<String, TeslaError> {
     println!("No default vehicle and no vehicle specified, please select:");
     let vehicles = client.get_vehicles().await;
     match vehicles {
@@ -408,9 +954,12 @@ async fn choose_vehicle(config: &mut Config, config_path: &PathBuf, client: Tesl
             config.global.default_vehicle_id = Some(v_list[i - 1].id);
             config.global.default_vehicle = Some(v_list[i - 1].display_name.clone());
 
-            if let Ok(str_content) = toml::to_string(config) {
-                let _ = fs::write(config_path, str_content);
-            }
+            // the token may have been refreshed and saved in the meantime, only update the vehicle
+            let global = config.global.clone();
+            update_config(config_path, |c| {
+                c.global.default_vehicle_id = global.default_vehicle_id;
+                c.global.default_vehicle = global.default_vehicle;
+            });
 
             Ok(v_list[i - 1].display_name.clone())
         }
@@ -421,7 +970,7 @@ async fn choose_vehicle(config: &mut Config, config_path: &PathBuf, client: Tesl
     }
 }
 
-async fn start_read_daemon(cfg: Config, config_path: &PathBuf, vehicle_name: String, client: TeslaClient) {
+async fn start_read_daemon(cfg: Config, vehicle_name: String, client: TeslaClient) {
     let sink = sink::new_sink(cfg.clone());
 
     if sink.is_none() {
@@ -436,7 +985,8 @@ async fn start_read_daemon(cfg: Config, config_path: &PathBuf, vehicle_name: Str
         .expect("could not find vehicle")
         .expect("could not find vehicle");
 
-    let mut vclient = client.vehicle(vehicle.id);
+    let vclient = client.vehicle(vehicle.id);
+    let energy_site = cfg.global.default_energy_site_id.map(|id| (id, client.energy_site(id)));
 
     let running = Arc::new(AtomicBool::new(true));
 
@@ -445,88 +995,82 @@ async fn start_read_daemon(cfg: Config, config_path: &PathBuf, vehicle_name: Str
         r.store(false, Ordering::SeqCst);
     }).expect("Error setting ctrl-c handler");
 
-    let default_poll_duration = 10; // default poll duration: 10s
-    let max_poll_duration = 600; // max poll duration: 10min
-    let mut poll_duration = default_poll_duration;
+    // transient API failures are retried by the client, only back off while the vehicle is asleep
+    // or the vehicle or the energy site keeps failing: 20s, 40s... up to 10min
+    let poll_interval = Duration::from_secs(10);
+    let idle_backoff = RetryPolicy::new(u32::MAX)
+        .initial_backoff(poll_interval * 2)
+        .max_backoff(Duration::from_secs(600));
+    let mut idle_polls = 0;
+    let mut energy_failures = 0;
 
-    let mut  next_poll_time = Instant::now();
+    let mut next_poll_time = Instant::now();
+    let mut next_energy_poll_time = Instant::now();
     while running.load(Ordering::SeqCst) {
         if Instant::now() > next_poll_time {
-            match try_refresh_token(cfg.clone(), config_path).await {
-                Ok(config) => {
-                    let new_client = TeslaClient::default(config.token.access_token.as_str());
-                    vclient = new_client.vehicle(vehicle.id);
-                }
-                Err(e) => {
-                    error!("fail to refresh token: {}", e);
-                }
-            }
             debug!("Reporting to sink");
-            if let Ok(v) = vclient.get().await {
-                if v.state == "online" {
-                    match vclient.get_all_data().await {
-                        Ok(d) => {
-                            sink.save(&d);
-                            poll_duration = default_poll_duration;
-                        }
-
-                        Err(e) => {
-                            error!("fail to fetch vehicle data: {}", e);
-                            poll_duration *= 2;
-                        }
+            let reported = match vclient.get().await {
+                Ok(v) if v.state == "online" => match vclient.get_all_data().await {
+                    Ok(d) => {
+                        sink.save(&Record::Vehicle(&d));
+                        true
                     }
-                } else {
+                    Err(e) => {
+                        error!("fail to fetch vehicle data: {}", e);
+                        false
+                    }
+                },
+                Ok(_) => {
                     info!("vehicle is not online, waiting");
-                    poll_duration *= 2;
+                    false
+                }
+                Err(e) => {
+                    error!("fail to fetch vehicle state: {}", e);
+                    false
                 }
+            };
+
+            let wait = if reported {
+                idle_polls = 0;
+                poll_interval
             } else {
-                error!("fail to fetch vehicle data");
-                poll_duration *= 2;
-            }
+                idle_polls += 1;
+                idle_backoff.backoff(idle_polls)
+            };
+            next_poll_time = Instant::now() + wait;
+        }
 
-            if poll_duration > max_poll_duration {
-                poll_duration = max_poll_duration;
+        if let Some((energy_site_id, site)) = &energy_site {
+            if Instant::now() > next_energy_poll_time {
+                debug!("Reporting energy site to sink");
+                let wait = match site.live_status().await {
+                    Ok(live_status) => {
+                        sink.save(&Record::EnergySite { energy_site_id: *energy_site_id, live_status: &live_status });
+                        energy_failures = 0;
+                        poll_interval
+                    }
+                    Err(e) => {
+                        error!("fail to fetch energy site status: {}", e);
+                        energy_failures += 1;
+                        idle_backoff.backoff(energy_failures)
+                    }
+                };
+                next_energy_poll_time = Instant::now() + wait;
             }
-
-            next_poll_time = Instant::now() + Duration::from_secs(poll_duration);
         }
 
-        sleep(Duration::from_millis(poll_duration));
+        sleep(Duration::from_millis(100));
     }
 
     sink.destroy();
 }
 
-async fn try_refresh_token(cfg: Config, config_path: &PathBuf) -> Result<Config, TeslaError> {
-    let expires_ts = cfg.token.expires_ts;
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-    if now.as_secs() >= expires_ts {
-        println!("Token expired, try to refresh it");
-        // token expired, refresh
-        let token_result = TeslaClient::refresh_token(cfg.token.refresh_token.as_str()).await;
-
-        match token_result {
-            Ok(token) => {
-                let mut c = cfg.clone();
-                c.token.access_token = token.access_token;
-                c.token.refresh_token = token.refresh_token;
-
-                // calculate new expire time
-                let new_expire_ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().add(Duration::from_secs(token.expires_in as u64));
-                c.token.expires_ts = new_expire_ts.as_secs();
-
-                if let Ok(str_content) = toml::to_string(&c) {
-                    let _ = fs::write(&config_path, str_content);
-                }
-
-                Ok(c)
-            }
-            Err(e) => {
-                eprintln!("failed to refresh token: {}", e);
-                Err(e)
-            }
-        }
-    } else {
-        Ok(cfg)
+/// Region to refresh against: the configured one, otherwise the issuer of the stored tokens.
+fn auth_region(cfg: &Config, token: Option<&StoredToken>) -> Result<AuthRegion, TeslaError> {
+    if let Some(region) = &cfg.global.auth_region {
+        return region.parse();
     }
+    Ok(token.and_then(|t| AuthRegion::from_token(t.access_token.as_str()))
+        .or_else(|| token.and_then(|t| AuthRegion::from_token(t.refresh_token.as_str())))
+        .unwrap_or_default())
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-use tesla::FullVehicleData;
+use tesla::{FullVehicleData, LiveStatus};
 use crate::config::Config;
 
 #[cfg(feature = "sqlite")]
@@ -6,8 +6,14 @@ mod sqlite;
 #[cfg(feature = "mqtt")]
 mod mqtt;
 
+/// Data polled by the daemon, to save.
+pub enum Record<'a> {
+    Vehicle(&'a FullVehicleData),
+    EnergySite { energy_site_id: u64, live_status: &'a LiveStatus },
+}
+
 pub trait Sink {
-    fn save(&mut self, vehicle_data: &FullVehicleData);
+    fn save(&mut self, record: &Record);
     fn destroy(&mut self);
 }
 
@@ -1,9 +1,9 @@
 use std::thread;
 use std::time::Duration;
 use rumqttc::{Client, MqttOptions, QoS};
-use tesla::FullVehicleData;
+use serde::Serialize;
 use crate::Config;
-use crate::sink::Sink;
+use crate::sink::{Record, Sink};
 
 pub struct MqttSink {
     client: Client,
@@ -31,10 +31,10 @@ impl MqttSink {
         })
     }
 
-    fn send(&mut self, vehicle_data: &FullVehicleData) {
-        if let Ok(json_content) = serde_json::to_string(vehicle_data) {
+    fn send<T: Serialize>(&mut self, topic: &str, data: &T) {
+        if let Ok(json_content) = serde_json::to_string(data) {
             info!("Sending data to MQTT: {}", json_content);
-            let publish_result = self.client.publish(&self.topic, QoS::AtLeastOnce, false, json_content.as_bytes());
+            let publish_result = self.client.publish(topic, QoS::AtLeastOnce, false, json_content.as_bytes());
 
             if let Err(e) = publish_result {
                 error!("Error publishing to MQTT: {:?}", e);
@@ -44,8 +44,18 @@ impl MqttSink {
 }
 
 impl Sink for MqttSink {
-    fn save(&mut self, vehicle_data: &FullVehicleData) {
-        self.send(vehicle_data);
+    // the energy sites are published next to the vehicle, ex: vehicle/tesla/energy_site/90001
+    fn save(&mut self, record: &Record) {
+        match record {
+            Record::Vehicle(vehicle_data) => {
+                let topic = self.topic.clone();
+                self.send(topic.as_str(), vehicle_data)
+            }
+            Record::EnergySite { energy_site_id, live_status } => {
+                let topic = format!("{}/energy_site/{}", self.topic, energy_site_id);
+                self.send(topic.as_str(), live_status)
+            }
+        }
     }
 
     fn destroy(&mut self) {
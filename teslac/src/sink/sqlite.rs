@@ -1,9 +1,9 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 use rusqlite::Connection;
 use rusqlite::params;
-use tesla::{FullVehicleData};
+use tesla::{FullVehicleData, LiveStatus};
 use crate::config::Config;
-use crate::sink::Sink;
+use crate::sink::{Record, Sink};
 
 pub struct SqliteSink {
     conn: Connection
@@ -11,6 +11,7 @@ pub struct SqliteSink {
 
 const INSERT_BATTERY: &str = "INSERT INTO battery(ts, level, range) VALUES(?,?,?)";
 const INSERT_DRIVER_STATE: &str = "INSERT INTO driver_state(ts, heading, latitude, longitude, power, speed) VALUES(?,?,?,?,?,?)";
+const INSERT_ENERGY: &str = "INSERT INTO energy(ts, site_id, solar_power, battery_power, grid_power, load_power, percentage_charged) VALUES(?,?,?,?,?,?,?)";
 
 impl SqliteSink {
     pub fn new(config: Config) -> Box<dyn Sink> {
@@ -22,6 +23,7 @@ impl SqliteSink {
             BEGIN;
             CREATE TABLE IF NOT EXISTS battery(ts INTEGER PRIMARY KEY, level INTEGER, range REAL) WITHOUT ROWID;
             CREATE TABLE IF NOT EXISTS driver_state(ts INTEGER PRIMARY KEY, heading INTEGER, latitude REAL, longitude REAL, power REAL, speed INTEGER) WITHOUT ROWID;
+            CREATE TABLE IF NOT EXISTS energy(ts INTEGER, site_id INTEGER, solar_power REAL, battery_power REAL, grid_power REAL, load_power REAL, percentage_charged REAL, PRIMARY KEY(ts, site_id)) WITHOUT ROWID;
             COMMIT;
         ");
 
@@ -29,13 +31,8 @@ impl SqliteSink {
             conn: connection
         })
     }
-}
-
-impl Sink for SqliteSink {
-    fn save(&mut self, vehicle_data: &FullVehicleData) {
-        let time = SystemTime::now();
-        let ts = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
 
+    fn save_vehicle(&mut self, ts: u64, vehicle_data: &FullVehicleData) {
         if let Ok(mut stmt) = self.conn.prepare_cached(INSERT_BATTERY) {
             let _ = stmt.execute(params![ts, vehicle_data.charge_state.battery_level, vehicle_data.charge_state.battery_range * 1.6]);
         }
@@ -46,6 +43,25 @@ impl Sink for SqliteSink {
         }
     }
 
+    fn save_energy(&mut self, ts: u64, energy_site_id: u64, live_status: &LiveStatus) {
+        if let Ok(mut stmt) = self.conn.prepare_cached(INSERT_ENERGY) {
+            let _ = stmt.execute(params![ts, energy_site_id, live_status.solar_power, live_status.battery_power,
+                live_status.grid_power, live_status.load_power, live_status.percentage_charged]);
+        }
+    }
+}
+
+impl Sink for SqliteSink {
+    fn save(&mut self, record: &Record) {
+        let time = SystemTime::now();
+        let ts = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        match record {
+            Record::Vehicle(vehicle_data) => self.save_vehicle(ts, vehicle_data),
+            Record::EnergySite { energy_site_id, live_status } => self.save_energy(ts, *energy_site_id, live_status),
+        }
+    }
+
     fn destroy(&mut self) {
     }
 }
\ No newline at end of file